@@ -1,21 +1,40 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use dotenvy::dotenv;
+use futures_util::{Stream, TryStreamExt};
+use phonenumber::country;
+use qrcode::{render::unicode::Dense1x2, QrCode};
 use std::{
     env,
-    fs::File,
+    fs::{self, File},
     io::{self, Read, Write},
     path::PathBuf,
+    pin::pin,
+    time::Duration,
+};
+use telechecker_lib::{
+    auth::{AuthPrompter, QrLogin, SentCode},
+    batch::{BatchOptions, DEFAULT_BATCH_DELAY, DEFAULT_CHUNK_SIZE},
+    builder::Proxy,
+    check::{CheckResult, LookupStrategy, ResultFile, RESULT_FILE_VERSION},
+    error::SignInFailure,
+    photos::PhotoOptions,
+    session::{is_encrypted, EncryptedSessionStore, FileSessionStore, MemorySessionStore},
+    Error, UserRetriever, JOURNAL_FILE, SESSION_FILE,
 };
-use telechecker_lib::{UserRetriever, SESSION_FILE};
 use tracing::{debug, info};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 const API_ID_ENV: &str = "API_ID";
 const API_HASH_ENV: &str = "API_HASH";
 const PHONE_NUMBER_ENV: &str = "PHONE_NUMBER";
+const PROXY_ENV: &str = "PROXY";
 const DEFAULT_OUTPUT_FILE: &str = "results.json";
 
+mod secret;
+
+use secret::SecretSource;
+
 /// Whether the added contact should be kept as the user's contact after retrieval
 #[cfg(debug_assertions)]
 const PRESERVE_CONTACT_DEFAULT: bool = true;
@@ -35,23 +54,111 @@ const DEFAULT_PRINT: bool = true;
 const DEFAULT_PRINT: bool = false;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    sign_in: SignIn,
+
+    #[clap(flatten)]
+    check: Check,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Signs in and saves the session without checking anything, so that later `check` runs do
+    /// not have to sign in again
+    Login,
+    /// Checks phone numbers or usernames (the default when no subcommand is given)
+    Check(Check),
+}
+
+// How the user signs in and where the session is kept, shared by every subcommand
+#[derive(Debug, clap::Args)]
+struct SignIn {
     /// User's phone number (associated with a Telegram account)
-    #[arg(short = 'u', long, value_name = "USER_PHONE_NUMBER")]
+    #[arg(short = 'u', long, value_name = "USER_PHONE_NUMBER", global = true)]
     user_phone: Option<String>,
 
-    #[clap(flatten)]
-    input: Input,
+    /// Whether to sign in by scanning a QR code from a device where the account is already signed
+    /// in (Settings > Devices > Link Desktop Device), rather than with a phone number and code.
+    /// Default: false
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "user_phone",
+        global = true
+    )]
+    qr_login: bool,
+
+    /// Where the user's phone number is read from if not provided with --user-phone or
+    /// PHONE_NUMBER: `prompt`, `env:NAME`, `file:PATH` (deleted once read), `pipe:PATH` or
+    /// `cmd:COMMAND`
+    #[arg(long, value_name = "SOURCE", default_value = "prompt", global = true)]
+    phone_from: SecretSource,
+
+    /// Where the login code is read from: `prompt`, `env:NAME`, `file:PATH` (waited for, and
+    /// deleted once read), `pipe:PATH` or `cmd:COMMAND`
+    #[arg(long, value_name = "SOURCE", default_value = "prompt", global = true)]
+    code_from: SecretSource,
+
+    /// Where the password (2FA) is read from, if the account has one: `prompt`, `env:NAME`,
+    /// `file:PATH` (deleted once read), `pipe:PATH` or `cmd:COMMAND`
+    #[arg(long, value_name = "SOURCE", default_value = "prompt", global = true)]
+    password_from: SecretSource,
+
+    /// Where the session passphrase is read from: `prompt`, `env:NAME`, `file:PATH` (deleted once
+    /// read), `pipe:PATH` or `cmd:COMMAND`
+    #[arg(long, value_name = "SOURCE", default_value = "prompt", global = true)]
+    passphrase_from: SecretSource,
+
+    /// SOCKS5 proxy every connection to Telegram goes through, as
+    /// `socks5://[username:password@]host:port`. If not provided, the PROXY inside the `.env`
+    /// file is used, if any
+    #[arg(long, value_name = PROXY_ENV, global = true)]
+    proxy: Option<Proxy>,
 
     /// User's api id. If not provided, the API_ID must be found inside the `.env` file
-    #[arg(long, value_name = API_ID_ENV)]
+    #[arg(long, value_name = API_ID_ENV, global = true)]
     api_id: Option<String>,
 
     /// User's api hash. If not provided, the API_HASH must be found inside the `.env` file
-    #[arg(long, value_name = API_HASH_ENV)]
+    #[arg(long, value_name = API_HASH_ENV, global = true)]
     api_hash: Option<String>,
 
+    /// Whether the session file should be kept unencrypted, rather than encrypted with a
+    /// passphrase prompted for on every run. Default: false
+    #[arg(long, default_value_t = false, global = true)]
+    plaintext_session: bool,
+
+    /// Whether the session should be discarded once done: the user signs in again on every run,
+    /// nothing is written to the session file and the session is logged out when the run ends.
+    /// Default: false
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "plaintext_session",
+        global = true
+    )]
+    ephemeral: bool,
+
+    /// Region (i.e. `US`, `GB`) assumed for phone numbers provided without a country code
+    #[arg(long, value_name = "REGION", value_parser = parse_region, global = true)]
+    region: Option<country::Id>,
+
+    /// Verbosity flag (counted): i.e. `-v`: little output -vvvv: lots of output
+    #[arg(short, default_value_t = DEFAULT_VERBOSITY, action = clap::ArgAction::Count, global = true)]
+    verbosity: u8,
+}
+
+// What is checked and how, when running `check` or no subcommand
+#[derive(Debug, clap::Args)]
+struct Check {
+    #[clap(flatten)]
+    input: Input,
+
     /// Output file name. Default: `results.json`
     #[arg(short = 'o', long, value_name = "OUTPUT_FILE")]
     output: Option<String>,
@@ -60,6 +167,46 @@ struct Cli {
     #[arg(long, default_value_t = PRESERVE_CONTACT_DEFAULT)]
     preserve_contact: bool,
 
+    /// How accounts are looked up: `import-contacts` adds the phone numbers to the user's
+    /// contacts in batches and removes them afterwards, `resolve-phone` sends one
+    /// `contacts.ResolvePhone` per phone number without modifying the contacts. Not supported
+    /// with --usernames
+    #[arg(long, value_name = "STRATEGY", default_value = "import-contacts", value_parser = parse_strategy, conflicts_with = "usernames")]
+    strategy: LookupStrategy,
+
+    /// Whether the profile photo thumbnail of every account found should be included as a base64
+    /// JPEG data URI. Sends no additional request
+    #[arg(long, default_value_t = false)]
+    thumbnails: bool,
+
+    /// Whether the full profile (bio, common chats count, ...) of every account found should be
+    /// retrieved. Sends one more request per account found. Not supported with --usernames
+    #[arg(long, default_value_t = false, conflicts_with = "usernames")]
+    full_profile: bool,
+
+    /// Directory the profile photos of every account found should be downloaded to. A new
+    /// directory is created inside it for every run. Not supported with --usernames
+    #[arg(long, value_name = "EVIDENCE_DIR", conflicts_with = "usernames")]
+    photos: Option<PathBuf>,
+
+    /// Whether every past profile photo should be downloaded, rather than only the current one
+    /// (requires --photos)
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "photos",
+        conflicts_with = "usernames"
+    )]
+    photo_history: bool,
+
+    /// Amount of phone numbers imported per request
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    batch_size: usize,
+
+    /// Delay between two import requests, in seconds
+    #[arg(long, default_value_t = DEFAULT_BATCH_DELAY.as_secs())]
+    batch_delay: u64,
+
     /// Whether the JSON output should be printed to stdout. Default: false
     #[arg(long, short, default_value_t = DEFAULT_PRINT)]
     print: bool,
@@ -68,10 +215,6 @@ struct Cli {
     /// to see configuration parameters)
     #[arg(short, long, default_value_t = false)]
     dry_run: bool,
-
-    /// Verbosity flag (counted): i.e. `-v`: little output -vvvv: lots of output
-    #[arg(short, default_value_t = DEFAULT_VERBOSITY, action = clap::ArgAction::Count)]
-    verbosity: u8,
 }
 
 #[derive(Debug, clap::Args)]
@@ -89,6 +232,28 @@ pub struct Input {
         value_delimiter = ','
     )]
     phone_numbers_args: Option<Vec<String>>,
+
+    /// Usernames to look up (i.e. `@handle` or `t.me/handle`) instead of phone numbers. Nothing
+    /// is added to the user's contacts
+    #[arg(long = "usernames", value_name = "USERNAMES", value_delimiter = ',')]
+    usernames: Option<Vec<String>>,
+}
+
+fn parse_region(region: &str) -> Result<country::Id, String> {
+    region
+        .to_uppercase()
+        .parse()
+        .map_err(|_| format!("unknown region '{region}', expected a code such as `US` or `GB`"))
+}
+
+fn parse_strategy(strategy: &str) -> Result<LookupStrategy, String> {
+    match strategy {
+        "import-contacts" => Ok(LookupStrategy::ImportContacts),
+        "resolve-phone" => Ok(LookupStrategy::ResolvePhone),
+        _ => Err(format!(
+            "unknown strategy '{strategy}', expected `import-contacts` or `resolve-phone`"
+        )),
+    }
 }
 
 async fn prompt(message: &str) -> Result<String> {
@@ -103,13 +268,192 @@ async fn prompt(message: &str) -> Result<String> {
     Ok(buf)
 }
 
-async fn prompt_pass(hint: String) -> Result<String> {
-    match rpassword::prompt_password(format!("Enter the password (hint: {}): ", hint))
-        .context("rpassword input")
+/// [`AuthPrompter`] reading what is needed to sign in from its [`SecretSource`]s, asking for it
+/// on the terminal by default
+struct TerminalPrompter {
+    /// The phone number provided on the command line, used on the first attempt only
+    phone: Option<String>,
+    /// Amount of times the phone number was asked for
+    phone_attempts: u32,
+    phone_from: SecretSource,
+    code_from: SecretSource,
+    password_from: SecretSource,
+}
+
+impl AuthPrompter for TerminalPrompter {
+    async fn phone(&mut self) -> Result<String> {
+        if let Some(phone) = self.phone.take() {
+            return Ok(phone);
+        }
+        self.phone_attempts += 1;
+        match self.phone_from.read("phone", self.phone_attempts).await? {
+            Some(phone) => Ok(phone),
+            None => prompt("Enter your phone number: ").await,
+        }
+    }
+
+    async fn code(&mut self, sent_code: &SentCode, attempt: u32) -> Result<String> {
+        if let Some(code) = self.code_from.read("code", attempt).await? {
+            return Ok(code);
+        }
+        prompt(&format!(
+            "Enter the code you received through {}: ",
+            sent_code.delivery
+        ))
+        .await
+    }
+
+    async fn resend(&mut self, sent_code: &SentCode) -> Result<bool> {
+        let Some(next) = sent_code.next else {
+            return Ok(false);
+        };
+        // only asked on the terminal, the code is read from its source again otherwise
+        if self.code_from != SecretSource::Prompt {
+            return Ok(false);
+        }
+        let answer = prompt(&format!("Send the code again through {next}? [y/N] ")).await?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    async fn password(&mut self, hint: Option<&str>, attempt: u32) -> Result<String> {
+        if let Some(password) = self.password_from.read("password", attempt).await? {
+            return Ok(password);
+        }
+        let message = format!("Enter the password (hint: {}): ", hint.unwrap_or("None"));
+        match rpassword::prompt_password(message).context("rpassword input") {
+            Ok(s) => Ok(s),
+            Err(_) => anyhow::bail!("Unable to retrieve password"),
+        }
+    }
+
+    async fn qr_login(&mut self, qr_login: &QrLogin) -> Result<()> {
+        display_qr_login(qr_login)
+    }
+
+    async fn notify_error(&mut self, error: &Error) {
+        eprintln!("{error}, try again");
+    }
+}
+
+/// Prints the QR code to scan to sign in
+fn display_qr_login(qr_login: &QrLogin) -> Result<()> {
+    let code = QrCode::new(qr_login.url.as_bytes()).context("encode QR code")?;
+    // terminals usually draw light text on a dark background, so the colors are swapped for
+    // the code to be read by scanners
+    let image = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!(
+        "Scan this QR code from a device where you are signed in (Settings > Devices > Link Desktop Device), it expires at {}:\n{image}",
+        qr_login.expires.to_rfc3339().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Returns the phone numbers and usernames to check
+fn read_input(input: &Input) -> Result<(Vec<String>, Option<Vec<String>>)> {
+    let usernames = input.usernames.clone();
+    let phone_numbers = match (&input.phone_numbers_args, &input.phone_numbers_file) {
+        (Some(v), _) => v.clone(),
+        (_, Some(path)) => {
+            let mut file = File::options().read(true).open(path)?;
+            let mut buf = String::new();
+            file.read_to_string(&mut buf)?;
+            buf.trim().lines().map(str::to_string).collect()
+        }
+        _ if usernames.is_some() => Vec::new(),
+        _ => anyhow::bail!("Must provide phone numbers or usernames"),
+    };
+    Ok((phone_numbers, usernames))
+}
+
+/// Reads the passphrase the session file is encrypted with from `source`, prompting for it by
+/// default. A passphrase chosen because no encrypted session exists yet is prompted for twice
+async fn read_passphrase(source: &SecretSource) -> Result<String> {
+    if let Some(passphrase) = source.read("passphrase", 1).await? {
+        return Ok(passphrase);
+    }
+    // a typo in a passphrase chosen to encrypt the session would lock the user out of it
+    let new_passphrase = !fs::read(SESSION_FILE).is_ok_and(|session| is_encrypted(&session));
+    let message = if new_passphrase {
+        "Choose a passphrase to encrypt the session: "
+    } else {
+        "Enter the session passphrase: "
+    };
+    let passphrase = rpassword::prompt_password(message).context("rpassword input")?;
+    if passphrase.is_empty() {
+        anyhow::bail!("The session passphrase cannot be empty");
+    }
+    if new_passphrase
+        && rpassword::prompt_password("Enter the passphrase again: ").context("rpassword input")?
+            != passphrase
     {
-        Ok(s) => Ok(s),
-        Err(_) => anyhow::bail!("Unable to retrieve password"),
+        anyhow::bail!("The passphrases do not match");
     }
+    Ok(passphrase)
+}
+
+/// Adds a hint on how to recover from the errors the user can do something about
+fn with_hint(error: Error) -> anyhow::Error {
+    let hint = match &error {
+        Error::InvalidApiCredentials(_) => "Check the provided API_ID and API_HASH",
+        Error::SignIn(SignInFailure::InvalidCode) => {
+            "The code was mistyped too many times, run again to receive a new one"
+        }
+        Error::SignIn(SignInFailure::InvalidPassword) => {
+            "The password was mistyped too many times, run again"
+        }
+        Error::SignIn(SignInFailure::SignUpRequired) => {
+            "The provided phone number is not associated with a telegram account"
+        }
+        Error::SessionEncryption => {
+            "Check the session passphrase, or remove the session file and sign in again"
+        }
+        Error::Unauthorized(_) => {
+            "The session is no longer valid, remove the session file and sign in again"
+        }
+        Error::RateLimited(_) => {
+            "Telegram rate limited this account, try again later or with a smaller --batch-size"
+        }
+        _ => return error.into(),
+    };
+    anyhow::Error::new(error).context(hint)
+}
+
+/// Writes each result to `out` as soon as it is received, so the results are kept if the check
+/// fails midway. The output is a [`telechecker_lib::check::ResultFile`], written by hand since it
+/// is never held in memory as a whole. Returns the amount of results written
+async fn write_results(
+    results: impl Stream<Item = telechecker_lib::Result<CheckResult>>,
+    out: &mut impl Write,
+    print: bool,
+) -> Result<usize> {
+    let mut results = pin!(results);
+    let mut written = 0;
+    write!(
+        out,
+        "{{\n  \"version\": {RESULT_FILE_VERSION},\n  \"results\": ["
+    )?;
+    let outcome = loop {
+        match results.try_next().await {
+            Ok(Some(result)) => {
+                let json = serde_json::to_string_pretty(&result).context("serde_json to_string")?;
+                if print {
+                    println!("{json}");
+                }
+                out.write_all(if written == 0 { b"\n    " } else { b",\n    " })?;
+                out.write_all(json.replace('\n', "\n    ").as_bytes())?;
+                out.flush()?;
+                written += 1;
+            }
+            Ok(None) => break Ok(written),
+            Err(e) => break Err(with_hint(e)),
+        }
+    };
+    out.write_all(if written == 0 { b"]\n}" } else { b"\n  ]\n}" })?;
+    outcome
 }
 
 #[tokio::main]
@@ -126,8 +470,22 @@ async fn main() -> Result<()> {
     .context("couldn't set current dir")?;
 
     let cli = Cli::parse();
+    let sign_in = cli.sign_in;
+    let input = &cli.check.input;
+    if cli.command.is_some()
+        && (input.phone_numbers_file.is_some()
+            || input.phone_numbers_args.is_some()
+            || input.usernames.is_some())
+    {
+        anyhow::bail!("Phone numbers and usernames must be provided after the subcommand");
+    }
+    let check = match cli.command {
+        Some(Command::Login) => None,
+        Some(Command::Check(check)) => Some(check),
+        None => Some(cli.check),
+    };
 
-    let verbosity = match cli.verbosity {
+    let verbosity = match sign_in.verbosity {
         0 => "error",
         1 => "warn",
         2 => "info",
@@ -147,17 +505,15 @@ async fn main() -> Result<()> {
         )))
         .init();
 
-    debug!("{cli:?}");
+    debug!("{sign_in:?}");
+    debug!("{check:?}");
 
-    let input = match (cli.input.phone_numbers_args, cli.input.phone_numbers_file) {
-        (Some(v), _) => v,
-        (_, Some(path)) => {
-            let mut file = File::options().read(true).open(path)?;
-            let mut buf = String::new();
-            file.read_to_string(&mut buf)?;
-            buf.trim().lines().map(str::to_string).collect()
+    let (input, usernames) = match &check {
+        Some(check) => read_input(&check.input)?,
+        None if sign_in.ephemeral => {
+            anyhow::bail!("`login` saves the session, it cannot be used with --ephemeral")
         }
-        _ => anyhow::bail!("Must provide phone numbers"),
+        None => Default::default(),
     };
 
     debug!("Input: {input:?}");
@@ -168,7 +524,7 @@ async fn main() -> Result<()> {
 
     let (api_id_env, api_hash_env) = (env::var(API_ID_ENV).ok(), env::var(API_HASH_ENV).ok());
 
-    let (api_id, api_hash) = match (cli.api_id, cli.api_hash) {
+    let (api_id, api_hash) = match (sign_in.api_id, sign_in.api_hash) {
         (Some(id), Some(hash)) => (Some(id), Some(hash)),
         (Some(id), None) => (Some(id), api_hash_env),
         (None, Some(hash)) => (api_id_env, Some(hash)),
@@ -182,7 +538,7 @@ async fn main() -> Result<()> {
         }
     };
 
-    let provided_user_phone_number = cli
+    let provided_user_phone_number = sign_in
         .user_phone
         .map_or_else(|| env::var(PHONE_NUMBER_ENV).ok(), Some);
 
@@ -193,57 +549,159 @@ async fn main() -> Result<()> {
 
     let api_id = api_id.parse::<i32>().context("parse API_ID as i32")?;
 
-    info!("Connecting to Telegram...");
+    let proxy = match sign_in.proxy {
+        Some(proxy) => Some(proxy),
+        None => env::var(PROXY_ENV)
+            .ok()
+            .map(|proxy| proxy.parse::<Proxy>())
+            .transpose()
+            .context("parse PROXY")?,
+    };
 
-    let mut user_retriever = UserRetriever::new(api_id, api_hash, SESSION_FILE).await?;
+    match &proxy {
+        Some(proxy) => info!("Connecting to Telegram through {proxy}..."),
+        None => info!("Connecting to Telegram..."),
+    }
 
-    let phone_input_handler = || async {
-        match provided_user_phone_number {
-            Some(pn) => Ok(pn),
-            None => prompt("Enter your phone number: ").await,
+    let builder = UserRetriever::builder(api_id, api_hash).proxy(proxy);
+    let user_retriever = if sign_in.ephemeral {
+        builder
+            .session_store(MemorySessionStore::new())
+            .connect()
+            .await
+    } else if sign_in.plaintext_session {
+        builder
+            .session_store(FileSessionStore::new(SESSION_FILE))
+            .connect()
+            .await
+    } else {
+        let passphrase = read_passphrase(&sign_in.passphrase_from).await?;
+        let store = EncryptedSessionStore::new(FileSessionStore::new(SESSION_FILE), passphrase);
+        if store.migrate()? {
+            info!("Encrypted the existing session file '{SESSION_FILE}'");
         }
+        builder.session_store(store).connect().await
     };
-    let code_input_handler = || prompt("Enter the code you received: ");
-    let password_input_handler = |hint: String| prompt_pass(hint);
-
-    user_retriever
-        .sign_in(
-            phone_input_handler,
-            code_input_handler,
-            password_input_handler,
-        )
-        .await?;
-
-    if cli.dry_run {
-        info!("Dry run complete. Input numbers: {:?}", input);
-        return Ok(());
+    let mut user_retriever = user_retriever
+        .map_err(with_hint)?
+        .with_ephemeral(sign_in.ephemeral);
+    if let Some(region) = sign_in.region {
+        user_retriever = user_retriever.with_default_region(region);
     }
 
-    let users: Vec<_> = user_retriever.get_users(input).await?.collect();
+    let mut prompter = TerminalPrompter {
+        phone: provided_user_phone_number,
+        phone_attempts: 0,
+        phone_from: sign_in.phone_from,
+        code_from: sign_in.code_from,
+        password_from: sign_in.password_from,
+    };
+    let signed_in = if sign_in.qr_login {
+        user_retriever.sign_in_qr(&mut prompter).await
+    } else {
+        user_retriever.sign_in(&mut prompter).await
+    };
+    signed_in.map_err(with_hint)?;
 
-    let output_file = cli.output.as_deref().unwrap_or(DEFAULT_OUTPUT_FILE);
-    let out = serde_json::to_string_pretty(&users).context("serde_json to_string")?;
+    let Some(check) = check else {
+        // the session is only logged out if it could not be saved
+        if user_retriever.sign_out().await.map_err(with_hint)? {
+            anyhow::bail!("Unable to save the session to '{SESSION_FILE}', logged out");
+        }
+        info!("Signed in, the session is saved to '{SESSION_FILE}'");
+        return Ok(());
+    };
 
-    if !cli.preserve_contact {
-        user_retriever.delete_contacts(users.as_slice()).await?;
-    } else {
-        debug!("Contacts preserved");
-    }
-    drop(user_retriever);
+    let mut user_retriever = user_retriever
+        .with_batch_options(BatchOptions {
+            chunk_size: check.batch_size,
+            delay: Duration::from_secs(check.batch_delay),
+            ..Default::default()
+        })
+        .with_lookup_strategy(check.strategy)
+        .with_thumbnails(check.thumbnails)
+        .with_full_users(check.full_profile)
+        .with_photos(check.photos.map(|evidence_dir| PhotoOptions {
+            evidence_dir,
+            history: check.photo_history,
+        }));
+
+    // Every run ends by signing out, so an ephemeral session is logged out even if it failed
+    let outcome: Result<()> = async {
+        if let Some(report) = user_retriever.recover_cleanup(JOURNAL_FILE).await? {
+            info!(
+                "Removed {} contacts left behind by a previous run",
+                report.deleted.len()
+            );
+        }
 
-    if cli.print {
-        println!("{out}");
-    }
+        if check.dry_run {
+            info!("Dry run complete. Input numbers: {input:?}, input usernames: {usernames:?}");
+            return Ok(());
+        }
 
-    info!("Writing output to '{output_file}'");
-    let mut file = File::options()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)?;
+        let output_file = check.output.as_deref().unwrap_or(DEFAULT_OUTPUT_FILE);
+        info!("Writing output to '{output_file}'");
+        let mut file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_file)?;
+
+        if let Some(usernames) = usernames {
+            let results = user_retriever
+                .resolve_usernames(usernames)
+                .await
+                .map_err(with_hint)?;
+            if check.print {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&results).context("serde_json to_string")?
+                );
+            }
+            let written = results.len();
+            ResultFile {
+                usernames: results,
+                ..ResultFile::new(Vec::new())
+            }
+            .write(&mut file)?;
+            info!("{written} results saved as '{output_file}'");
+            return Ok(());
+        }
 
-    file.write_all(out.as_bytes())?;
-    info!("Results saved as '{output_file}'");
+        // The added contacts are removed even if the check fails
+        let (written, report) = user_retriever
+            .with_import_session(JOURNAL_FILE, async |session| {
+                if check.preserve_contact {
+                    session.keep_contacts();
+                }
+                write_results(session.get_users_stream(input), &mut file, check.print).await
+            })
+            .await?;
+
+        match report {
+            None => debug!("Contacts preserved"),
+            Some(report) => {
+                debug!("Removed {} contacts", report.deleted.len());
+                if !report.skipped.is_empty() {
+                    info!(
+                        "Kept {} accounts that were already contacts: {:?}",
+                        report.skipped.len(),
+                        report.skipped
+                    );
+                }
+            }
+        }
 
-    Ok(())
+        let written = written?;
+        info!("{written} results saved as '{output_file}'");
+        Ok(())
+    }
+    .await;
+
+    if user_retriever.sign_out().await.map_err(with_hint)? {
+        info!("Logged out");
+    }
+
+    outcome
 }
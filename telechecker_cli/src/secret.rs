@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use std::{env, io::ErrorKind, path::PathBuf, process::Stdio, str::FromStr, time::Duration};
+use tokio::{fs, process::Command, time::Instant};
+
+/// Delay between two checks for a secret file to be created
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a secret file is waited for before giving up
+const FILE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Environment variable telling a secret command which secret is asked for
+const SECRET_NAME_ENV: &str = "TELECHECKER_SECRET";
+/// Environment variable telling a secret command how many times the secret was asked for
+const SECRET_ATTEMPT_ENV: &str = "TELECHECKER_ATTEMPT";
+
+/// Where a secret needed to sign in (the phone number, login code, password or session
+/// passphrase) is read from, so the CLI can run without a terminal (i.e. from cron or CI)
+///
+/// Parsed from `prompt`, `env:NAME`, `file:PATH`, `pipe:PATH` or `cmd:COMMAND`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SecretSource {
+    /// Asked for on the terminal
+    #[default]
+    Prompt,
+    /// Read from the environment variable `NAME`. Only read once, since it cannot change if the
+    /// secret is rejected
+    Env(String),
+    /// Read from a file, which is deleted once read. The file is waited for if it does not
+    /// exist yet (i.e. until the login code is written to it)
+    File(PathBuf),
+    /// Read from a named pipe, waiting for a writer every time the secret is asked for
+    Pipe(PathBuf),
+    /// Printed by a command run through the shell, every time the secret is asked for. The
+    /// command receives the name of the secret (`phone`, `code`, `password` or `passphrase`) in
+    /// `TELECHECKER_SECRET` and the attempt in `TELECHECKER_ATTEMPT`
+    Command(String),
+}
+
+impl FromStr for SecretSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source == "prompt" {
+            return Ok(Self::Prompt);
+        }
+        let (kind, value) = source.split_once(':').unwrap_or((source, ""));
+        if value.is_empty() {
+            return Err(format!(
+                "invalid secret source '{source}', expected `prompt`, `env:NAME`, `file:PATH`, `pipe:PATH` or `cmd:COMMAND`"
+            ));
+        }
+        match kind {
+            "env" => Ok(Self::Env(value.to_string())),
+            "file" => Ok(Self::File(value.into())),
+            "pipe" => Ok(Self::Pipe(value.into())),
+            "cmd" => Ok(Self::Command(value.to_string())),
+            _ => Err(format!(
+                "unknown secret source '{kind}', expected `prompt`, `env`, `file`, `pipe` or `cmd`"
+            )),
+        }
+    }
+}
+
+impl SecretSource {
+    /// Reads the secret `name` for the `attempt`th time, or returns [`None`] if it must be asked
+    /// for on the terminal
+    pub async fn read(&self, name: &str, attempt: u32) -> Result<Option<String>> {
+        let secret = match self {
+            Self::Prompt => return Ok(None),
+            Self::Env(var) => {
+                if attempt > 1 {
+                    anyhow::bail!("The {name} read from `{var}` was rejected");
+                }
+                env::var(var).with_context(|| format!("read the {name} from `{var}`"))?
+            }
+            Self::File(path) => {
+                let secret = read_file(path)
+                    .await
+                    .with_context(|| format!("read the {name} from '{}'", path.display()))?;
+                fs::remove_file(path)
+                    .await
+                    .with_context(|| format!("remove '{}'", path.display()))?;
+                secret
+            }
+            Self::Pipe(path) => fs::read_to_string(path)
+                .await
+                .with_context(|| format!("read the {name} from '{}'", path.display()))?,
+            Self::Command(command) => run(command, name, attempt)
+                .await
+                .with_context(|| format!("read the {name} from `{command}`"))?,
+        };
+        let secret = secret.trim();
+        if secret.is_empty() {
+            anyhow::bail!("The {name} is empty");
+        }
+        Ok(Some(secret.to_string()))
+    }
+}
+
+/// Reads the file at `path`, waiting for it to be created for up to [`FILE_TIMEOUT`]
+async fn read_file(path: &PathBuf) -> Result<String> {
+    let deadline = Instant::now() + FILE_TIMEOUT;
+    loop {
+        match fs::read_to_string(path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound && Instant::now() < deadline => {
+                tokio::time::sleep(FILE_POLL_INTERVAL).await;
+            }
+            secret => return Ok(secret?),
+        }
+    }
+}
+
+/// Runs `command` through the shell, returning what it printed
+async fn run(command: &str, name: &str, attempt: u32) -> Result<String> {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .env(SECRET_NAME_ENV, name)
+        .env(SECRET_ATTEMPT_ENV, attempt.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .await?;
+    if !output.status.success() {
+        anyhow::bail!("the command exited with {}", output.status);
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_source() {
+        for (source, expected) in [
+            ("prompt", SecretSource::Prompt),
+            ("env:TG_CODE", SecretSource::Env("TG_CODE".into())),
+            ("file:/tmp/code", SecretSource::File("/tmp/code".into())),
+            (
+                "pipe:/tmp/code.fifo",
+                SecretSource::Pipe("/tmp/code.fifo".into()),
+            ),
+            (
+                "cmd:pass show telegram:code",
+                SecretSource::Command("pass show telegram:code".into()),
+            ),
+        ] {
+            assert_eq!(source.parse(), Ok(expected), "{source}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_or_empty_sources() {
+        assert!("vault:code"
+            .parse::<SecretSource>()
+            .unwrap_err()
+            .starts_with("unknown secret source 'vault'"));
+        for source in ["", "env", "env:", "file:", "secret"] {
+            assert!(
+                source
+                    .parse::<SecretSource>()
+                    .unwrap_err()
+                    .starts_with("invalid secret source"),
+                "{source}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn prompt_is_left_to_the_terminal() {
+        assert_eq!(SecretSource::Prompt.read("code", 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn env_is_read_once() {
+        // set by cargo for every test binary
+        let source = SecretSource::Env("CARGO_PKG_NAME".into());
+
+        assert_eq!(
+            source.read("code", 1).await.unwrap().as_deref(),
+            Some("telechecker_cli")
+        );
+        assert!(source.read("code", 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_env_var_fails() {
+        let source = SecretSource::Env("TELECHECKER_TEST_UNSET_VARIABLE".into());
+
+        let error = source.read("password", 1).await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "read the password from `TELECHECKER_TEST_UNSET_VARIABLE`"
+        );
+    }
+
+    #[tokio::test]
+    async fn file_is_trimmed_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("code");
+        std::fs::write(&path, "12345\n").unwrap();
+        let source = SecretSource::File(path.clone());
+
+        assert_eq!(
+            source.read("code", 1).await.unwrap().as_deref(),
+            Some("12345")
+        );
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn pipe_is_read_every_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("code");
+        std::fs::write(&path, "12345\r\n").unwrap();
+        let source = SecretSource::Pipe(path.clone());
+
+        assert_eq!(
+            source.read("code", 1).await.unwrap().as_deref(),
+            Some("12345")
+        );
+        assert_eq!(
+            source.read("code", 2).await.unwrap().as_deref(),
+            Some("12345")
+        );
+        assert!(path.exists());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn command_output_is_trimmed() {
+        let source =
+            SecretSource::Command(r#"echo "$TELECHECKER_SECRET-$TELECHECKER_ATTEMPT""#.into());
+
+        assert_eq!(
+            source.read("code", 2).await.unwrap().as_deref(),
+            Some("code-2")
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn failing_or_silent_commands_fail() {
+        assert!(SecretSource::Command("exit 1".into())
+            .read("code", 1)
+            .await
+            .is_err());
+        assert_eq!(
+            SecretSource::Command("echo".into())
+                .read("code", 1)
+                .await
+                .unwrap_err()
+                .to_string(),
+            "The code is empty"
+        );
+    }
+}
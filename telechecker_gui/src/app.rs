@@ -1,5 +1,7 @@
 use crate::comms::CommChannelsApp;
-use crate::UserData;
+use crate::{CheckResult, LookupStrategy, SESSION_FILE};
+use std::fs;
+use telechecker_lib::session::is_encrypted;
 
 pub struct Telegather {
     pub config: Config,
@@ -9,13 +11,20 @@ pub struct Telegather {
     pub cache: Cache,
     pub connection_status: ConnectionStatus,
     pub comm_channels: CommChannelsApp,
-    pub user_data: Option<Vec<UserData>>,
+    /// Results of the current check, received one at a time
+    pub user_data: Vec<CheckResult>,
+    /// The QR code to scan to sign in, along with the URL it encodes
+    pub qr_texture: Option<(String, egui::TextureHandle)>,
+    /// Whether no encrypted session exists yet, so the passphrase is chosen rather than entered
+    pub new_passphrase: bool,
 }
 
 pub struct Config {
     pub hide_phone: bool,
     pub hide_code: bool,
     pub hide_pass: bool,
+    /// How the target phone numbers are looked up
+    pub lookup_strategy: LookupStrategy,
 }
 
 impl Default for Config {
@@ -24,6 +33,7 @@ impl Default for Config {
             hide_phone: false,
             hide_code: false,
             hide_pass: true,
+            lookup_strategy: LookupStrategy::default(),
         }
     }
 }
@@ -32,18 +42,26 @@ impl Default for Config {
 pub enum ConnectionStatus {
     #[default]
     RequiresApiInfo,
+    AwaitingPassphrase,
     NotConnected,
     AwaitingPhoneNumber,
+    AwaitingQrScan,
     AwaitingUserCode,
     AwaitingPassword,
     Authorized,
+    LoggedOut,
 }
 
 pub struct Cache {
     pub user_phone: String,
     pub user_code: String,
     pub user_pwd: String,
+    pub passphrase: String,
+    /// The passphrase entered again, when it is chosen
+    pub passphrase_confirmation: String,
     pub phones_input: String,
+    /// The proxy URL being edited in the settings
+    pub proxy: String,
     pub api_id: String,
     pub api_hash: String,
 }
@@ -54,7 +72,10 @@ impl Cache {
             user_phone: String::new(),
             user_code: String::new(),
             user_pwd: String::new(),
+            passphrase: String::new(),
+            passphrase_confirmation: String::new(),
             phones_input: String::new(),
+            proxy: String::new(),
             api_id: String::new(),
             api_hash: String::new(),
         }
@@ -63,15 +84,21 @@ impl Cache {
 
 impl Telegather {
     pub fn new(comm_channels: CommChannelsApp) -> Self {
+        let mut cache = Cache::new();
+        if let Some(proxy) = comm_channels.proxy_tx.borrow().as_ref() {
+            cache.proxy = proxy.url();
+        }
         Self {
             config: Default::default(),
             user_phone: None,
             error_message: None,
             info_message: None,
-            cache: Cache::new(),
+            cache,
             connection_status: Default::default(),
             comm_channels,
-            user_data: None,
+            user_data: Vec::new(),
+            qr_texture: None,
+            new_passphrase: !fs::read(SESSION_FILE).is_ok_and(|session| is_encrypted(&session)),
         }
     }
 }
@@ -1,9 +1,19 @@
 use crate::warn;
-use crate::Receiver;
+use crate::CheckResult;
 use crate::UserRetriever;
-use crate::{comms::CommChannelsClient, info};
-use crate::{debug, ConnectionStatus};
+use crate::{
+    comms::{CommChannelsClient, SignInMethod},
+    info,
+};
+use crate::{debug, describe_lib_error, ConnectionStatus, Error, QrLogin, JOURNAL_FILE};
 use anyhow::Result;
+use futures_util::{Stream, TryStreamExt};
+use std::pin::pin;
+use telechecker_lib::{
+    auth::{AuthPrompter, CodeDelivery, SentCode},
+    session::{EncryptedSessionStore, FileSessionStore},
+};
+use tokio::sync::{mpsc, watch};
 
 #[tokio::main]
 #[tracing::instrument(skip_all)]
@@ -15,16 +25,22 @@ pub async fn client_handler(
 ) -> Result<()> {
     let CommChannelsClient {
         api_info_rx,
-        user_phone_rx,
+        proxy_rx,
+        passphrase_rx,
+        sign_in_method_rx,
         code_receiver_rx,
+        resend_code_rx,
+        resend_offer_tx,
         pass_recieve_rx,
+        mut log_out_rx,
         mut input_phones_rx,
         user_data_tx,
         connection_status_tx,
+        qr_login_tx,
+        auth_prompt_tx,
+        auth_error_tx,
     } = comms_channel;
 
-    let tx = connection_status_tx.clone();
-
     let (api_id, api_hash) = match (api_id2, api_hash2) {
         (Some(id), Some(hash)) => (id, hash),
         _ => {
@@ -36,66 +52,236 @@ pub async fn client_handler(
         }
     };
 
+    connection_status_tx.send(ConnectionStatus::AwaitingPassphrase)?;
+    let Ok(passphrase) = passphrase_rx.await else {
+        anyhow::bail!("Unable to retrieve session passphrase");
+    };
+    let session_store = EncryptedSessionStore::new(FileSessionStore::new(session_file), passphrase);
+    if session_store.migrate()? {
+        info!("Encrypted the existing session file '{session_file}'");
+    }
+
     connection_status_tx.send(ConnectionStatus::NotConnected)?;
 
-    let mut user_retriever = UserRetriever::new(api_id, api_hash, session_file).await?;
+    let proxy = proxy_rx.borrow().clone();
+    if let Some(proxy) = &proxy {
+        info!("Connecting through {proxy}");
+    }
+    let mut user_retriever = UserRetriever::builder(api_id, api_hash)
+        .session_store(session_store)
+        .proxy(proxy)
+        .connect()
+        .await?;
     debug!("UserRetriever built");
 
-    let user_phone_input_handler = || async move {
-        tx.send(ConnectionStatus::AwaitingPhoneNumber)?;
-        phone_input_handler(user_phone_rx).await
-    };
-    let tx = connection_status_tx.clone();
-    let code_input_handler = || async move {
-        tx.send(ConnectionStatus::AwaitingUserCode)?;
-        code_input_handler(code_receiver_rx).await
-    };
-    let tx = connection_status_tx.clone();
-    let password_input_handler = |_hint: String| async move {
-        tx.send(ConnectionStatus::AwaitingPassword)?;
-        password_input_handler(pass_recieve_rx).await
-    };
-    user_retriever
-        .sign_in(
-            user_phone_input_handler,
-            code_input_handler,
-            password_input_handler,
-        )
-        .await?;
+    if user_retriever.is_authorized().await? {
+        debug!("User is already authorized");
+    } else {
+        connection_status_tx.send(ConnectionStatus::AwaitingPhoneNumber)?;
+        let mut prompter = GuiPrompter {
+            phone: None,
+            code: None,
+            sign_in_method_rx,
+            code_receiver_rx,
+            resend_code_rx,
+            resend_offer_tx,
+            pass_recieve_rx,
+            connection_status_tx: connection_status_tx.clone(),
+            auth_prompt_tx,
+            auth_error_tx,
+            qr_login_tx,
+        };
+        match sign_in_method_handler(&mut prompter.sign_in_method_rx).await? {
+            SignInMethod::Phone(phone) => {
+                prompter.phone = Some(phone);
+                user_retriever.sign_in(&mut prompter).await?;
+            }
+            SignInMethod::QrCode => {
+                connection_status_tx.send(ConnectionStatus::AwaitingQrScan)?;
+                user_retriever.sign_in_qr(&mut prompter).await?;
+            }
+        }
+    }
     connection_status_tx.send(ConnectionStatus::Authorized)?;
+    if let Some(report) = user_retriever.recover_cleanup(JOURNAL_FILE).await? {
+        info!(
+            "Removed {} contacts left behind by a previous run",
+            report.deleted.len()
+        );
+    }
     debug!("Signed in. Waiting for phone numbers");
-    while let Some(phone_numbers) = input_phones_rx.recv().await {
-        let users: Vec<_> = user_retriever.get_users(phone_numbers).await?.collect();
-        debug!("Received numbers, sending");
-        debug!("Returned users length: {}", users.len());
-        if user_data_tx.send(users).await.is_err() {
+    loop {
+        let (phone_numbers, strategy) = tokio::select! {
+            input = input_phones_rx.recv() => match input {
+                Some(input) => input,
+                None => break,
+            },
+            Ok(()) = &mut log_out_rx => {
+                user_retriever.log_out().await?;
+                info!("Logged out and removed the session file '{session_file}'");
+                connection_status_tx.send(ConnectionStatus::LoggedOut)?;
+                return Ok(());
+            }
+        };
+        user_retriever = user_retriever.with_lookup_strategy(strategy);
+        // The added contacts are removed even if the check fails
+        let (checked, report) = user_retriever
+            .with_import_session(JOURNAL_FILE, async |session| {
+                send_results(session.get_users_stream(phone_numbers), &user_data_tx).await
+            })
+            .await?;
+        if let Some(report) = report {
+            debug!("Removed {} contacts", report.deleted.len());
+        }
+        if !checked? {
             debug!("Cannot sent any more users");
             break;
-        };
+        }
     }
+    user_retriever.sign_out().await?;
 
     Ok(())
 }
 
-async fn phone_input_handler(user_phone_rx: Receiver<String>) -> Result<String> {
-    let Ok(phone) = user_phone_rx.await else {
-        anyhow::bail!("Unable to retrieve user phone");
-    };
-    info!("phone handler: {phone}");
-    Ok(phone)
+/// Sends each result to the app as soon as it is received. Returns `false` if the app stopped
+/// receiving them
+async fn send_results(
+    results: impl Stream<Item = telechecker_lib::Result<CheckResult>>,
+    user_data_tx: &mpsc::Sender<CheckResult>,
+) -> telechecker_lib::Result<bool> {
+    let mut results = pin!(results);
+    while let Some(result) = results.try_next().await? {
+        if user_data_tx.send(result).await.is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
-async fn code_input_handler(code_receiver_rx: Receiver<String>) -> Result<String> {
-    let Ok(code) = code_receiver_rx.await else {
-        anyhow::bail!("Unable to retrieve user code");
+async fn sign_in_method_handler(
+    sign_in_method_rx: &mut mpsc::Receiver<SignInMethod>,
+) -> Result<SignInMethod> {
+    let Some(method) = sign_in_method_rx.recv().await else {
+        anyhow::bail!("Unable to retrieve sign in method");
     };
-    Ok(code)
+    if let SignInMethod::Phone(phone) = &method {
+        info!("phone handler: {phone}");
+    }
+    Ok(method)
 }
 
-async fn password_input_handler(pass_recieve_rx: Receiver<String>) -> Result<String> {
-    let out = match pass_recieve_rx.await {
-        Ok(pwd) => pwd,
-        Err(_) => anyhow::bail!("Unable to retrieve user password"),
-    };
-    Ok(out)
+/// [`AuthPrompter`] asking the app for what is needed to sign in
+struct GuiPrompter {
+    /// The phone number chosen as the sign in method, used on the first attempt only
+    phone: Option<String>,
+    /// The code entered while the code could be sent again, used on the next attempt
+    code: Option<String>,
+    sign_in_method_rx: mpsc::Receiver<SignInMethod>,
+    code_receiver_rx: mpsc::Receiver<String>,
+    resend_code_rx: mpsc::Receiver<()>,
+    resend_offer_tx: watch::Sender<Option<CodeDelivery>>,
+    pass_recieve_rx: mpsc::Receiver<String>,
+    connection_status_tx: watch::Sender<ConnectionStatus>,
+    auth_prompt_tx: watch::Sender<Option<String>>,
+    auth_error_tx: mpsc::Sender<String>,
+    qr_login_tx: watch::Sender<Option<QrLogin>>,
+}
+
+impl GuiPrompter {
+    /// Asks the app for `status`, describing it with `prompt`
+    fn request(&self, status: ConnectionStatus, prompt: String) -> Result<()> {
+        self.auth_prompt_tx.send(Some(prompt))?;
+        self.connection_status_tx.send(status)?;
+        Ok(())
+    }
+}
+
+impl AuthPrompter for GuiPrompter {
+    async fn phone(&mut self) -> Result<String> {
+        if let Some(phone) = self.phone.take() {
+            return Ok(phone);
+        }
+        self.request(
+            ConnectionStatus::AwaitingPhoneNumber,
+            "Input your telegram-associated phone number again:".to_string(),
+        )?;
+        match sign_in_method_handler(&mut self.sign_in_method_rx).await? {
+            SignInMethod::Phone(phone) => Ok(phone),
+            SignInMethod::QrCode => anyhow::bail!("Unable to retrieve user phone"),
+        }
+    }
+
+    async fn code(&mut self, sent_code: &SentCode, attempt: u32) -> Result<String> {
+        if let Some(code) = self.code.take() {
+            return Ok(code);
+        }
+        let prompt = if attempt == 1 {
+            format!(
+                "Enter the code you received through {}:",
+                sent_code.delivery
+            )
+        } else {
+            format!(
+                "Enter the code you received through {} again:",
+                sent_code.delivery
+            )
+        };
+        self.request(ConnectionStatus::AwaitingUserCode, prompt)?;
+        let Some(code) = self.code_receiver_rx.recv().await else {
+            anyhow::bail!("Unable to retrieve user code");
+        };
+        Ok(code)
+    }
+
+    async fn resend(&mut self, sent_code: &SentCode) -> Result<bool> {
+        let Some(next) = sent_code.next else {
+            return Ok(false);
+        };
+        self.request(
+            ConnectionStatus::AwaitingUserCode,
+            format!(
+                "Enter the code you received through {} again, or send it again through {next}:",
+                sent_code.delivery
+            ),
+        )?;
+        // ignore clicks left over from a previous offer
+        while self.resend_code_rx.try_recv().is_ok() {}
+        self.resend_offer_tx.send(Some(next))?;
+        let resend = tokio::select! {
+            code = self.code_receiver_rx.recv() => {
+                let Some(code) = code else {
+                    anyhow::bail!("Unable to retrieve user code");
+                };
+                self.code = Some(code);
+                false
+            }
+            resend = self.resend_code_rx.recv() => resend.is_some(),
+        };
+        self.resend_offer_tx.send(None)?;
+        Ok(resend)
+    }
+
+    async fn password(&mut self, hint: Option<&str>, _attempt: u32) -> Result<String> {
+        let prompt = match hint {
+            Some(hint) => format!("Password required (hint: {hint}):"),
+            None => "Password required:".to_string(),
+        };
+        self.request(ConnectionStatus::AwaitingPassword, prompt)?;
+        let Some(password) = self.pass_recieve_rx.recv().await else {
+            anyhow::bail!("Unable to retrieve user password");
+        };
+        Ok(password)
+    }
+
+    async fn qr_login(&mut self, qr_login: &QrLogin) -> Result<()> {
+        self.qr_login_tx.send(Some(qr_login.clone()))?;
+        Ok(())
+    }
+
+    async fn notify_error(&mut self, error: &Error) {
+        let description = describe_lib_error(error).unwrap_or_else(|| error.to_string());
+        if self.auth_error_tx.send(description).await.is_err() {
+            debug!("App already exited");
+        }
+    }
 }
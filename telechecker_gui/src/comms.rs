@@ -1,4 +1,4 @@
-use crate::UserData;
+use crate::{CheckResult, CodeDelivery, LookupStrategy, Proxy, QrLogin};
 use anyhow::Error;
 use tokio::sync::{
     mpsc,
@@ -9,42 +9,76 @@ use tokio::sync::{
 
 use crate::{ConnectionStatus, CHANNEL_BUFFER_SIZE};
 
-pub fn create_comms_channels() -> (CommChannelsApp, CommChannelsClient, Sender<Error>) {
+pub fn create_comms_channels(
+    proxy: Option<Proxy>,
+) -> (CommChannelsApp, CommChannelsClient, Sender<Error>) {
     // Sender is app
     let (api_info_tx, api_info_rx) = oneshot::channel();
     // Sender is app
-    let (user_phone_tx, user_phone_rx) = oneshot::channel();
+    let (proxy_tx, proxy_rx) = watch::channel(proxy);
+    // Sender is app
+    let (passphrase_tx, passphrase_rx) = oneshot::channel();
+    // Sender is app
+    let (sign_in_method_tx, sign_in_method_rx) = channel(CHANNEL_BUFFER_SIZE);
     // Sender is app
     let (input_phones_tx, input_phones_rx) = channel(CHANNEL_BUFFER_SIZE);
     // Sender is runtime
     let (user_data_tx, user_data_rx) = channel(CHANNEL_BUFFER_SIZE);
     // Sender is app
-    let (code_receive_tx, code_receive_rx) = oneshot::channel();
+    let (code_receive_tx, code_receive_rx) = channel(CHANNEL_BUFFER_SIZE);
+    // Sender is app
+    let (resend_code_tx, resend_code_rx) = channel(CHANNEL_BUFFER_SIZE);
+    // Sender is runtime
+    let (resend_offer_tx, resend_offer_rx) = watch::channel(None);
+    // Sender is app
+    let (pass_receive_tx, pass_receive_rx) = channel(CHANNEL_BUFFER_SIZE);
     // Sender is app
-    let (pass_receive_tx, pass_receive_rx) = oneshot::channel();
+    let (log_out_tx, log_out_rx) = oneshot::channel();
     let (connection_status_tx, connection_status_rx) = watch::channel(ConnectionStatus::default());
+    // Sender is runtime
+    let (qr_login_tx, qr_login_rx) = watch::channel(None);
+    // Sender is runtime
+    let (auth_prompt_tx, auth_prompt_rx) = watch::channel(None);
+    // Sender is runtime
+    let (auth_error_tx, auth_error_rx) = channel(CHANNEL_BUFFER_SIZE);
 
     let (client_exit_error_tx, client_exit_error_rx) = oneshot::channel();
 
     let comm_channels_app = CommChannelsApp::new(
         api_info_tx,
-        user_phone_tx,
+        proxy_tx,
+        passphrase_tx,
+        sign_in_method_tx,
         code_receive_tx,
+        resend_code_tx,
+        resend_offer_rx,
         input_phones_tx,
         user_data_rx,
         pass_receive_tx,
+        log_out_tx,
         connection_status_rx,
+        qr_login_rx,
+        auth_prompt_rx,
+        auth_error_rx,
         client_exit_error_rx,
     );
 
     let comms_channels_client = CommChannelsClient::new(
         api_info_rx,
-        user_phone_rx,
+        proxy_rx,
+        passphrase_rx,
+        sign_in_method_rx,
         code_receive_rx,
+        resend_code_rx,
+        resend_offer_tx,
         input_phones_rx,
         user_data_tx,
         pass_receive_rx,
+        log_out_rx,
         connection_status_tx,
+        qr_login_tx,
+        auth_prompt_tx,
+        auth_error_tx,
     );
     (
         comm_channels_app,
@@ -53,14 +87,35 @@ pub fn create_comms_channels() -> (CommChannelsApp, CommChannelsClient, Sender<E
     )
 }
 
+/// How the user chose to sign in
+pub enum SignInMethod {
+    /// With this phone number, and the code sent to it
+    Phone(String),
+    /// By scanning a QR code from a device where they are already signed in
+    QrCode,
+}
+
 pub struct CommChannelsApp {
     pub api_info_tx: Option<Sender<(i32, String)>>,
-    pub user_phone_tx: Option<Sender<String>>,
-    pub user_code_tx: Option<Sender<String>>,
-    pub input_phones_tx: mpsc::Sender<Vec<String>>,
-    pub user_data_rx: mpsc::Receiver<Vec<UserData>>,
-    pub pass_receive_tx: Option<Sender<String>>,
+    /// The proxy to connect through, read by the runtime when connecting
+    pub proxy_tx: watch::Sender<Option<Proxy>>,
+    pub passphrase_tx: Option<Sender<String>>,
+    pub sign_in_method_tx: mpsc::Sender<SignInMethod>,
+    pub user_code_tx: mpsc::Sender<String>,
+    /// Asks the runtime to send the login code again, when it offers to
+    pub resend_code_tx: mpsc::Sender<()>,
+    /// How the login code would be sent again, while the runtime offers to send it again
+    pub resend_offer_rx: watch::Receiver<Option<CodeDelivery>>,
+    pub input_phones_tx: mpsc::Sender<(Vec<String>, LookupStrategy)>,
+    pub user_data_rx: mpsc::Receiver<CheckResult>,
+    pub pass_receive_tx: mpsc::Sender<String>,
+    pub log_out_tx: Option<Sender<()>>,
     pub connection_status_rx: watch::Receiver<ConnectionStatus>,
+    pub qr_login_rx: watch::Receiver<Option<QrLogin>>,
+    /// What the runtime is asking for while signing in, i.e. how the code was sent
+    pub auth_prompt_rx: watch::Receiver<Option<String>>,
+    /// Sign in errors the user is asked to correct, i.e. an invalid code
+    pub auth_error_rx: mpsc::Receiver<String>,
     pub client_exit_error_rx: Receiver<Error>,
 }
 
@@ -68,22 +123,38 @@ impl CommChannelsApp {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         api_info_tx: Sender<(i32, String)>,
-        user_phone_tx: Sender<String>,
-        user_code_tx: Sender<String>,
-        input_phones_tx: mpsc::Sender<Vec<String>>,
-        user_data_rx: mpsc::Receiver<Vec<UserData>>,
-        pass_receive_tx: Sender<String>,
+        proxy_tx: watch::Sender<Option<Proxy>>,
+        passphrase_tx: Sender<String>,
+        sign_in_method_tx: mpsc::Sender<SignInMethod>,
+        user_code_tx: mpsc::Sender<String>,
+        resend_code_tx: mpsc::Sender<()>,
+        resend_offer_rx: watch::Receiver<Option<CodeDelivery>>,
+        input_phones_tx: mpsc::Sender<(Vec<String>, LookupStrategy)>,
+        user_data_rx: mpsc::Receiver<CheckResult>,
+        pass_receive_tx: mpsc::Sender<String>,
+        log_out_tx: Sender<()>,
         connection_status_rx: watch::Receiver<ConnectionStatus>,
+        qr_login_rx: watch::Receiver<Option<QrLogin>>,
+        auth_prompt_rx: watch::Receiver<Option<String>>,
+        auth_error_rx: mpsc::Receiver<String>,
         client_exit_error_rx: Receiver<Error>,
     ) -> Self {
         Self {
             api_info_tx: Some(api_info_tx),
-            user_phone_tx: Some(user_phone_tx),
-            user_code_tx: Some(user_code_tx),
+            proxy_tx,
+            passphrase_tx: Some(passphrase_tx),
+            sign_in_method_tx,
+            user_code_tx,
+            resend_code_tx,
+            resend_offer_rx,
             input_phones_tx,
             user_data_rx,
-            pass_receive_tx: Some(pass_receive_tx),
+            pass_receive_tx,
+            log_out_tx: Some(log_out_tx),
             connection_status_rx,
+            qr_login_rx,
+            auth_prompt_rx,
+            auth_error_rx,
             client_exit_error_rx,
         }
     }
@@ -91,32 +162,57 @@ impl CommChannelsApp {
 
 pub struct CommChannelsClient {
     pub api_info_rx: Receiver<(i32, String)>,
-    pub user_phone_rx: Receiver<String>,
-    pub code_receiver_rx: Receiver<String>,
-    pub pass_recieve_rx: Receiver<String>,
-    pub input_phones_rx: mpsc::Receiver<Vec<String>>,
-    pub user_data_tx: mpsc::Sender<Vec<UserData>>,
+    pub proxy_rx: watch::Receiver<Option<Proxy>>,
+    pub passphrase_rx: Receiver<String>,
+    pub sign_in_method_rx: mpsc::Receiver<SignInMethod>,
+    pub code_receiver_rx: mpsc::Receiver<String>,
+    pub resend_code_rx: mpsc::Receiver<()>,
+    pub resend_offer_tx: watch::Sender<Option<CodeDelivery>>,
+    pub pass_recieve_rx: mpsc::Receiver<String>,
+    pub log_out_rx: Receiver<()>,
+    pub input_phones_rx: mpsc::Receiver<(Vec<String>, LookupStrategy)>,
+    pub user_data_tx: mpsc::Sender<CheckResult>,
     pub connection_status_tx: watch::Sender<ConnectionStatus>,
+    pub qr_login_tx: watch::Sender<Option<QrLogin>>,
+    pub auth_prompt_tx: watch::Sender<Option<String>>,
+    pub auth_error_tx: mpsc::Sender<String>,
 }
 
 impl CommChannelsClient {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         api_info_rx: Receiver<(i32, String)>,
-        user_phone_rx: Receiver<String>,
-        code_receiver_rx: Receiver<String>,
-        input_phones_rx: mpsc::Receiver<Vec<String>>,
-        user_data_tx: mpsc::Sender<Vec<UserData>>,
-        pass_recieve_rx: Receiver<String>,
+        proxy_rx: watch::Receiver<Option<Proxy>>,
+        passphrase_rx: Receiver<String>,
+        sign_in_method_rx: mpsc::Receiver<SignInMethod>,
+        code_receiver_rx: mpsc::Receiver<String>,
+        resend_code_rx: mpsc::Receiver<()>,
+        resend_offer_tx: watch::Sender<Option<CodeDelivery>>,
+        input_phones_rx: mpsc::Receiver<(Vec<String>, LookupStrategy)>,
+        user_data_tx: mpsc::Sender<CheckResult>,
+        pass_recieve_rx: mpsc::Receiver<String>,
+        log_out_rx: Receiver<()>,
         connection_status_tx: watch::Sender<ConnectionStatus>,
+        qr_login_tx: watch::Sender<Option<QrLogin>>,
+        auth_prompt_tx: watch::Sender<Option<String>>,
+        auth_error_tx: mpsc::Sender<String>,
     ) -> Self {
         Self {
             api_info_rx,
-            user_phone_rx,
+            proxy_rx,
+            passphrase_rx,
+            sign_in_method_rx,
             code_receiver_rx,
+            resend_code_rx,
+            resend_offer_tx,
             pass_recieve_rx,
+            log_out_rx,
             input_phones_rx,
             user_data_tx,
             connection_status_tx,
+            qr_login_tx,
+            auth_prompt_tx,
+            auth_error_tx,
         }
     }
 }
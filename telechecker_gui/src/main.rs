@@ -3,8 +3,13 @@
 use anyhow::{Context, Result};
 use eframe::egui;
 use std::{env, thread};
-use telechecker_lib::{user::UserData, validate, UserRetriever, SESSION_FILE};
-use tokio::sync::oneshot::Receiver;
+use telechecker_lib::{
+    auth::{CodeDelivery, QrLogin},
+    builder::Proxy,
+    check::{CheckResult, LookupStrategy, ResultFile},
+    error::SignInFailure,
+    validate, Error, UserRetriever, JOURNAL_FILE, SESSION_FILE,
+};
 use tracing::{debug, info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
@@ -15,6 +20,7 @@ pub const MIN_APP_SIZE: [f32; 2] = [220.0, 340.0];
 
 const API_ID_ENV: &str = "API_ID";
 const API_HASH_ENV: &str = "API_HASH";
+const PROXY_ENV: &str = "PROXY";
 const DEFAULT_OUTPUT_FILE: &str = "results.json";
 const CHANNEL_BUFFER_SIZE: usize = 5;
 const USER_PHONE_INPUT_ERROR: &str = "Unable to validate phone number. Check that your phone number is correctly formatted as an international phone number (i.e. +1-(404)-123-4567)";
@@ -102,8 +108,19 @@ fn main() -> Result<()> {
         env::var(API_HASH_ENV).ok(),
     );
 
+    // an invalid proxy is ignored so it can be fixed from the settings
+    let proxy = env::var(PROXY_ENV)
+        .ok()
+        .and_then(|proxy| match proxy.parse::<Proxy>() {
+            Ok(proxy) => Some(proxy),
+            Err(e) => {
+                warn!("Ignoring {PROXY_ENV}: {e}");
+                None
+            }
+        });
+
     // comms to allow app thread to communicate with async thread
-    let (comms_app, comms_client, client_exit_error_tx) = create_comms_channels();
+    let (comms_app, comms_client, client_exit_error_tx) = create_comms_channels(proxy);
 
     // spawn the async runtime thread
     let t = thread::spawn(move || {
@@ -139,17 +156,73 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Returns a message describing why the client exited, for the errors the user can do something
+/// about
+fn describe_error(error: &anyhow::Error) -> String {
+    error
+        .downcast_ref::<Error>()
+        .and_then(describe_lib_error)
+        .unwrap_or_else(|| format!("Client exited with an error: {error:?}"))
+}
+
+/// Returns a message describing `error`, if it is one the user can do something about
+fn describe_lib_error(error: &Error) -> Option<String> {
+    let description = match error {
+        Error::InvalidApiCredentials(_) => {
+            "Telegram rejected the API id or API hash, check your API info".to_string()
+        }
+        Error::InvalidPhone { .. } => USER_PHONE_INPUT_ERROR.to_string(),
+        Error::Rpc { name, .. } if name == "PHONE_NUMBER_INVALID" => {
+            "Telegram rejected this phone number".to_string()
+        }
+        Error::SignIn(SignInFailure::InvalidCode) => "The code is invalid".to_string(),
+        Error::SignIn(SignInFailure::CodeExpired) => {
+            "The code expired, a new one was sent".to_string()
+        }
+        Error::SignIn(SignInFailure::InvalidPassword) => "The password is invalid".to_string(),
+        Error::SignIn(SignInFailure::SignUpRequired) => {
+            "This phone number is not associated with a telegram account".to_string()
+        }
+        Error::SessionEncryption => {
+            format!("The session passphrase is wrong, or remove '{SESSION_FILE}' and sign in again")
+        }
+        Error::Unauthorized(_) => {
+            format!("The session is no longer valid, remove '{SESSION_FILE}' and sign in again")
+        }
+        Error::RateLimited(wait) => format!(
+            "Telegram rate limited this account, retry in {} seconds",
+            wait.as_secs()
+        ),
+        Error::Connection(_) | Error::Network(_) => {
+            "Unable to reach Telegram, check your connection".to_string()
+        }
+        _ => return None,
+    };
+    Some(description)
+}
+
 impl eframe::App for Telegather {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let connection_status = self.comm_channels.connection_status_rx.borrow_and_update();
         if connection_status.has_changed() {
             self.connection_status = *connection_status;
+            // the phone number is asked for again after an invalid one
+            if self.connection_status == ConnectionStatus::AwaitingPhoneNumber
+                && self.comm_channels.auth_prompt_rx.borrow().is_some()
+            {
+                self.user_phone = None;
+            }
         }
         drop(connection_status);
 
+        if let Ok(e) = self.comm_channels.auth_error_rx.try_recv() {
+            self.error_message = Some(e);
+        }
+
         if let Ok(e) = self.comm_channels.client_exit_error_rx.try_recv() {
             self.error_message = Some(format!(
-                "Client exited with an error: {e:?}\nPlease restart the app and try again"
+                "{}\nPlease restart the app and try again",
+                describe_error(&e)
             ));
         }
 
@@ -1,14 +1,23 @@
-use egui::{Color32, Label, Layout, TextEdit};
-use std::{fs::File, io::Write};
+use egui::{Color32, ColorImage, Label, Layout, TextEdit, TextureHandle, TextureOptions};
+use qrcode::QrCode;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    time::Duration,
+};
 use tracing::{error, info, warn};
 
 use crate::{
-    validate, ConnectionStatus, Telegather, APP_VERSION, DEFAULT_OUTPUT_FILE,
-    USER_PHONE_INPUT_ERROR,
+    comms::SignInMethod, validate, ConnectionStatus, LookupStrategy, Proxy, ResultFile, Telegather,
+    API_HASH_ENV, API_ID_ENV, APP_VERSION, DEFAULT_OUTPUT_FILE, PROXY_ENV, USER_PHONE_INPUT_ERROR,
 };
 
 const CONFIRM_BUTTON_TEXT: &str = "confirm";
 const GREEN_CHECK_EMOJI: &str = "✅";
+const RESULTS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const QR_CODE_SIZE: f32 = 200.;
+/// Width of the blank border around the QR code, in modules, for scanners to find it
+const QR_QUIET_ZONE: usize = 4;
 // const EDIT_EMOJI: &str = "📝";
 
 impl Telegather {
@@ -32,6 +41,14 @@ impl Telegather {
 
     pub fn central_ui(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
+            // the settings are applied when connecting
+            if matches!(
+                self.connection_status,
+                ConnectionStatus::RequiresApiInfo | ConnectionStatus::AwaitingPassphrase
+            ) {
+                self.settings_ui(ui);
+                ui.separator();
+            }
             match self.connection_status {
                 ConnectionStatus::RequiresApiInfo => {
                     ui.label("Telechecker requires API info:");
@@ -57,18 +74,10 @@ impl Telegather {
                         if !self.cache.api_id.is_empty() && !self.cache.api_hash.is_empty() {
                             if ui.small_button("Save to .env").clicked() {
                                 if let Ok(api_id) = self.cache.api_id.parse::<i32>() {
-                                if let Ok(mut env) = File::options()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(".env") {
-                                    let buf = format!("API_ID='{}'\nAPI_HASH='{}'", api_id, self.cache.api_hash);
-                                    match env.write_all(buf.as_bytes()) {
+                                    match save_env(&[(API_ID_ENV, &api_id.to_string()), (API_HASH_ENV, &self.cache.api_hash)]) {
                                         Ok(()) => self.info_message = Some(".env saved".into()),
                                         Err(e) => self.error_message = Some(format!("Unable to save .env. Error: {e}")),
                                     }
-
-                                }
                                 }
                             }
                             if ui.small_button(GREEN_CHECK_EMOJI).clicked() {
@@ -92,11 +101,49 @@ impl Telegather {
                         }
                     });
                 }
+                ConnectionStatus::AwaitingPassphrase => {
+                    if self.new_passphrase {
+                        ui.label("Choose a passphrase to encrypt the session, and enter it again:");
+                    } else {
+                        ui.label("Enter the passphrase the session is encrypted with:");
+                    }
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            TextEdit::singleline(&mut self.cache.passphrase)
+                                .desired_width(ui.available_width() / 3.)
+                                .password(true),
+                        );
+                        if self.new_passphrase {
+                            ui.add(
+                                TextEdit::singleline(&mut self.cache.passphrase_confirmation)
+                                    .desired_width(ui.available_width() / 2.)
+                                    .hint_text("again")
+                                    .password(true),
+                            );
+                        }
+                        if ui.small_button(GREEN_CHECK_EMOJI).clicked() {
+                            if self.cache.passphrase.is_empty() {
+                                self.error_message = Some("The session passphrase cannot be empty".to_string());
+                            } else if self.new_passphrase && self.cache.passphrase_confirmation != self.cache.passphrase {
+                                self.cache.passphrase_confirmation.clear();
+                                self.error_message = Some("The passphrases do not match".to_string());
+                            } else if let Some(sender) = self.comm_channels.passphrase_tx.take() {
+                                self.cache.passphrase_confirmation.clear();
+                                if let Err(e) = sender.send(std::mem::take(&mut self.cache.passphrase)) {
+                                    warn!("Unable to send session passphrase: {e:?}");
+                                } else {
+                                    self.info_message = Some("Passphrase sent, connecting...".to_string());
+                                }
+                            }
+                        }
+                    });
+                }
                 ConnectionStatus::NotConnected | ConnectionStatus::AwaitingPhoneNumber=> {
                     // Handle phone input
+                    let prompt = self.comm_channels.auth_prompt_rx.borrow().clone();
                     if self.user_phone.is_none() {
                         ui.vertical(|ui| {
-                            ui.label("Input your telegram-associated phone number:");
+                            ui.label(prompt.as_deref().unwrap_or("Input your telegram-associated phone number:"));
                         });
                         ui.horizontal(|ui| {
                             ui.add(
@@ -111,24 +158,50 @@ impl Telegather {
                                         self.error_message =
                                             Some(USER_PHONE_INPUT_ERROR.to_string())
                                     }
-                                    Ok(()) => self.user_phone = Some(self.cache.user_phone.clone()),
+                                    Ok(()) => {
+                                        let phone = self.cache.user_phone.clone();
+                                        if self.comm_channels.sign_in_method_tx.blocking_send(SignInMethod::Phone(phone.clone())).is_err() {
+                                            warn!("Unable to send user phone. Is the user already authorized?");
+                                        } else {
+                                            self.info_message = Some("Phone number sent, awaiting response...".to_string());
+                                            self.user_phone = Some(phone);
+                                        }
+                                    }
                                 }
                             }
                         });
-                    }
-                    if let Some(p) = self.user_phone.as_ref() {
-                        if let Some(sender) = self.comm_channels.user_phone_tx.take() {
-                            if let Err(e) = sender.send(p.clone()) {
-                                warn!("Unable to send user phone. Is the user already authorized?: {e:?}");
-                            } else {
-                                self.info_message = Some("Phone number sent, awaiting response...".to_string());
+                        // the QR code can only be chosen before the first phone number is sent
+                        if prompt.is_none() {
+                            ui.add_space(5.);
+                            if ui.small_button("sign in with a QR code instead")
+                                .on_hover_text("Scan a QR code from a device where you are already signed in, without providing your phone number")
+                                .clicked()
+                                && self.comm_channels.sign_in_method_tx.blocking_send(SignInMethod::QrCode).is_err() {
+                                warn!("Unable to send sign in method. Is the user already authorized?");
                             }
                         }
                     }
                 }
+                ConnectionStatus::AwaitingQrScan => {
+                    // a new QR code is issued every time the previous one expires
+                    ctx.request_repaint_after(RESULTS_POLL_INTERVAL);
+                    ui.label("On a device where you are signed in, open Settings > Devices > Link Desktop Device and scan this QR code:");
+                    let url = self.comm_channels.qr_login_rx.borrow().as_ref().map(|qr_login| qr_login.url.clone());
+                    if let Some(url) = url {
+                        if self.qr_texture.as_ref().is_none_or(|(shown, _)| *shown != url) {
+                            self.qr_texture = qr_texture(ctx, &url).map(|texture| (url, texture));
+                        }
+                    }
+                    if let Some((_, texture)) = &self.qr_texture {
+                        ui.vertical_centered(|ui| {
+                            ui.image((texture.id(), egui::vec2(QR_CODE_SIZE, QR_CODE_SIZE)));
+                        });
+                    }
+                }
 
                 ConnectionStatus::AwaitingUserCode => {
-                    ui.label("Enter the code when you receive the text:");
+                    let prompt = self.comm_channels.auth_prompt_rx.borrow().clone();
+                    ui.label(prompt.as_deref().unwrap_or("Enter the code when you receive the text:"));
                     ui.horizontal(|ui| {
                         ui.add(
                             TextEdit::singleline(&mut self.cache.user_code)
@@ -136,19 +209,27 @@ impl Telegather {
                                 .desired_width(ui.available_width() / 3.),
                         );
                         if ui.small_button(GREEN_CHECK_EMOJI).clicked() {
-                            if let Some(sender) = self.comm_channels.user_code_tx.take() {
-                                if let Err(e) = sender.send(self.cache.user_code.clone()) {
-                                    warn!("Unable to send user code. Is the user already authorized?: {e:?}");
+                            if let Err(e) = self.comm_channels.user_code_tx.blocking_send(std::mem::take(&mut self.cache.user_code)) {
+                                warn!("Unable to send user code. Is the user already authorized?: {e:?}");
+                            } else {
+                                self.info_message = Some("Code sent, awaiting response...".to_string());
+                            }
+                        }
+                        let resend_offer = *self.comm_channels.resend_offer_rx.borrow();
+                        if let Some(next) = resend_offer {
+                            if ui.small_button(format!("Send again through {next}")).clicked() {
+                                if let Err(e) = self.comm_channels.resend_code_tx.blocking_send(()) {
+                                    warn!("Unable to ask for the code again. Is the user already authorized?: {e:?}");
                                 } else {
-                                    self.info_message = Some("Code sent, awaiting response...".to_string());
+                                    self.info_message = Some("Sending the code again...".to_string());
                                 }
-                                self.cache.user_code.clear();
                             }
                         }
                     });
                 }
                 ConnectionStatus::AwaitingPassword => {
-                    ui.label("Password required:");
+                    let prompt = self.comm_channels.auth_prompt_rx.borrow().clone();
+                    ui.label(prompt.as_deref().unwrap_or("Password required:"));
                     ui.horizontal(|ui| {
                         ui.add(
                             TextEdit::singleline(&mut self.cache.user_pwd)
@@ -156,79 +237,107 @@ impl Telegather {
                                 .password(self.config.hide_pass),
                         );
                         if ui.small_button(GREEN_CHECK_EMOJI).clicked() {
-                            if let Some(sender) = self.comm_channels.pass_receive_tx.take() {
-                                if let Err(e) = sender.send(self.cache.user_pwd.clone()) {
-                                    warn!("Unable to send user password. Is the user already authorized?: {e:?}");
-                                } else {
-                                    self.info_message = Some("Password sent, awaiting response...".to_string());
-                                }
-                                self.cache.user_pwd.clear();
+                            if let Err(e) = self.comm_channels.pass_receive_tx.blocking_send(std::mem::take(&mut self.cache.user_pwd)) {
+                                warn!("Unable to send user password. Is the user already authorized?: {e:?}");
+                            } else {
+                                self.info_message = Some("Password sent, awaiting response...".to_string());
                             }
                         }
                     });
                 }
                 ConnectionStatus::Authorized => {
+                    // keep polling for results while a check is running
+                    ctx.request_repaint_after(RESULTS_POLL_INTERVAL);
                     ui.vertical_centered(|ui| {
                         ui.label("Please input target phone numbers:");
                         ui.text_edit_multiline(&mut self.cache.phones_input);
+                        ui.horizontal(|ui| {
+                            ui.label("Lookup:");
+                            ui.radio_value(
+                                &mut self.config.lookup_strategy,
+                                LookupStrategy::ImportContacts,
+                                "import as contacts",
+                            )
+                            .on_hover_text("Checks many numbers at once, temporarily adding them to your contacts");
+                            ui.radio_value(
+                                &mut self.config.lookup_strategy,
+                                LookupStrategy::ResolvePhone,
+                                "resolve phone",
+                            )
+                            .on_hover_text("Checks one number at a time, without modifying your contacts");
+                        });
                         if ui.button(CONFIRM_BUTTON_TEXT).clicked() {
-                            // validate
+                            self.user_data.clear();
+                            // invalid numbers are still sent so they show up in the results
                             let phonenumbers = self
                                 .cache
                                 .phones_input
                                 .lines()
-                                .filter_map(|n| {
-                                    if validate(n).is_ok() {
-                                        Some(n.to_string())
-                                    } else {
+                                .map(str::trim)
+                                .filter(|n| !n.is_empty())
+                                .inspect(|n| {
+                                    if validate(n).is_err() {
                                         warn!("Phone number '{n}' is invalid");
-                                        None
                                     }
                                 })
+                                .map(str::to_string)
                                 .collect();
                             if let Err(e) = self.comm_channels
                                 .input_phones_tx
-                                .blocking_send(phonenumbers) {
+                                .blocking_send((phonenumbers, self.config.lookup_strategy)) {
                                 error!("Encountered error sending input phone numbers: {e:?}");
                             }
                         }
+                        ui.add_space(10.);
+                        if ui.small_button("log out")
+                            .on_hover_text("Logs this session out and removes the session file")
+                            .clicked() {
+                            if let Some(sender) = self.comm_channels.log_out_tx.take() {
+                                if sender.send(()).is_err() {
+                                    warn!("Unable to send log out request");
+                                }
+                            }
+                        }
                     });
                 }
+                ConnectionStatus::LoggedOut => {
+                    ui.label("Logged out and removed the session. Restart the app to sign in again");
+                }
             }
 
-            if let Ok(user_data) = self.comm_channels.user_data_rx.try_recv() {
-                self.user_data = Some(user_data);
+            // results arrive as each batch completes, the output file is rewritten with every
+            // result received so far
+            let mut received = false;
+            while let Ok(result) = self.comm_channels.user_data_rx.try_recv() {
+                self.user_data.push(result);
+                received = true;
             }
 
-            if let Some(ref mut user_data)  = self.user_data  {
-                if !user_data.is_empty() {
-                    ui.separator();
-                    ui.add_space(5.);
-                        match serde_json::to_string_pretty(user_data) {
-                            Ok(out) => {
-                                info!("Writing output to '{DEFAULT_OUTPUT_FILE}'");
-                                if let Ok(mut file) = File::options()
-                                    .write(true)
-                                    .create(true)
-                                    .truncate(true)
-                                    .open(DEFAULT_OUTPUT_FILE)
-                                {
-                                    if file.write_all(out.as_bytes()).is_err() {
-                                        let fail = format!("Unable to write to file '{DEFAULT_OUTPUT_FILE}'");
-                                        warn!(fail);
-                                        self.info_message = Some(fail);
-                                        user_data.clear();
-                                    } else {
-                                        let success = format!("Results saved as '{DEFAULT_OUTPUT_FILE}'");
-                                        info!(success);
-                                        self.info_message = Some(success);
-                                        user_data.clear();
-                                    }
+            if received {
+                ui.separator();
+                ui.add_space(5.);
+                    match serde_json::to_string_pretty(&ResultFile::new(self.user_data.clone())) {
+                        Ok(out) => {
+                            info!("Writing output to '{DEFAULT_OUTPUT_FILE}'");
+                            if let Ok(mut file) = File::options()
+                                .write(true)
+                                .create(true)
+                                .truncate(true)
+                                .open(DEFAULT_OUTPUT_FILE)
+                            {
+                                if file.write_all(out.as_bytes()).is_err() {
+                                    let fail = format!("Unable to write to file '{DEFAULT_OUTPUT_FILE}'");
+                                    warn!(fail);
+                                    self.info_message = Some(fail);
+                                } else {
+                                    let success = format!("{} results saved as '{DEFAULT_OUTPUT_FILE}'", self.user_data.len());
+                                    info!(success);
+                                    self.info_message = Some(success);
                                 }
                             }
-                            Err(_) => error!("Failed to serialize UserData"),
                         }
-                }
+                        Err(_) => error!("Failed to serialize ResultFile"),
+                    }
             }
 
             if let Some(ref info_message) = self.info_message {
@@ -261,19 +370,84 @@ impl Telegather {
         });
     }
 
+    /// Settings applied when connecting to Telegram
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Settings", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("SOCKS5 proxy:");
+                ui.add(
+                    TextEdit::singleline(&mut self.cache.proxy)
+                        .desired_width(ui.available_width())
+                        .hint_text("socks5://[username:password@]host:port")
+                        .password(self.config.hide_pass),
+                );
+            });
+            ui.horizontal(|ui| {
+                if ui.small_button("apply").clicked() {
+                    self.apply_proxy();
+                }
+                if ui.small_button("Save to .env").clicked() && self.apply_proxy() {
+                    let url = self
+                        .comm_channels
+                        .proxy_tx
+                        .borrow()
+                        .as_ref()
+                        .map(Proxy::url);
+                    match save_env(&[(PROXY_ENV, url.as_deref().unwrap_or_default())]) {
+                        Ok(()) => self.info_message = Some(".env saved".into()),
+                        Err(e) => {
+                            self.error_message = Some(format!("Unable to save .env. Error: {e}"))
+                        }
+                    }
+                }
+            });
+            if self.cache.proxy.contains('@') {
+                ui.colored_label(
+                    Color32::YELLOW,
+                    "Saving to .env writes the proxy username and password to disk in plaintext",
+                );
+            }
+        });
+    }
+
+    /// Sends the proxy entered in the settings to the runtime, returning whether it is valid. An
+    /// empty proxy connects directly
+    fn apply_proxy(&mut self) -> bool {
+        let proxy = self.cache.proxy.trim();
+        let proxy = if proxy.is_empty() {
+            None
+        } else {
+            match proxy.parse::<Proxy>() {
+                Ok(proxy) => Some(proxy),
+                Err(e) => {
+                    self.error_message = Some(e.to_string());
+                    return false;
+                }
+            }
+        };
+        self.info_message = Some(match &proxy {
+            Some(proxy) => format!("Telegram will be reached through {proxy}"),
+            None => "Telegram will be reached directly".to_string(),
+        });
+        self.comm_channels.proxy_tx.send_replace(proxy);
+        true
+    }
+
     pub fn bottom_ui(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::bottom("bottom panel").show(ctx, |ui| {
             ui.horizontal_centered(|ui| {
                 ui.label("Status: ");
                 let as_str = Into::<&'static str>::into(self.connection_status);
                 match self.connection_status {
-                    ConnectionStatus::RequiresApiInfo => {
+                    ConnectionStatus::RequiresApiInfo | ConnectionStatus::LoggedOut => {
                         ui.colored_label(Color32::GRAY, as_str);
                     }
                     ConnectionStatus::NotConnected => {
                         ui.colored_label(Color32::DARK_GRAY, as_str);
                     }
-                    ConnectionStatus::AwaitingPhoneNumber
+                    ConnectionStatus::AwaitingPassphrase
+                    | ConnectionStatus::AwaitingPhoneNumber
+                    | ConnectionStatus::AwaitingQrScan
                     | ConnectionStatus::AwaitingUserCode
                     | ConnectionStatus::AwaitingPassword => {
                         ui.colored_label(Color32::YELLOW, as_str);
@@ -287,3 +461,59 @@ impl Telegather {
         });
     }
 }
+
+/// Sets `vars` in the `.env` file, keeping the other variables it holds. Empty values are removed.
+/// The values are written in plaintext, single-quoted so that dotenv reads them back verbatim
+fn save_env(vars: &[(&str, &str)]) -> io::Result<()> {
+    let env = match fs::read_to_string(".env") {
+        Ok(env) => env,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut lines: Vec<String> = env
+        .lines()
+        .filter(|line| {
+            !vars
+                .iter()
+                .any(|(name, _)| line.trim_start().starts_with(&format!("{name}=")))
+        })
+        .map(str::to_string)
+        .collect();
+    for (name, value) in vars {
+        if !value.is_empty() {
+            lines.push(format!("{name}={}", quote_env(value)));
+        }
+    }
+    let mut env = File::create(".env")?;
+    env.write_all(lines.join("\n").as_bytes())
+}
+
+/// Quotes `value` for a `.env` file. Single-quoted text is read literally, so quotes and newlines
+/// are written outside of the quotes, escaped
+fn quote_env(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''").replace('\n', r"'\n'"))
+}
+
+/// Draws the QR code encoding `url` into a texture, black on white
+fn qr_texture(ctx: &egui::Context, url: &str) -> Option<TextureHandle> {
+    let code = match QrCode::new(url.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Unable to encode the QR code: {e:?}");
+            return None;
+        }
+    };
+    let width = code.width();
+    let size = width + 2 * QR_QUIET_ZONE;
+    let mut pixels = vec![Color32::WHITE; size * size];
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == qrcode::Color::Dark {
+            pixels[(i / width + QR_QUIET_ZONE) * size + i % width + QR_QUIET_ZONE] = Color32::BLACK;
+        }
+    }
+    let image = ColorImage {
+        size: [size, size],
+        pixels,
+    };
+    Some(ctx.load_texture("qr login", image, TextureOptions::NEAREST))
+}
@@ -0,0 +1,156 @@
+use crate::{user::Timestamp, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use grammers_tl_types as tl;
+use std::{
+    fmt,
+    future::{ready, Future},
+};
+
+/// Retrieves what is needed to sign the requesting user in from some form of input (see
+/// [`crate::UserRetriever::sign_in`] and [`crate::UserRetriever::sign_in_qr`])
+///
+/// Invalid phone numbers, codes and passwords are reported with
+/// [`AuthPrompter::notify_error`] and asked for again, up to a few attempts
+pub trait AuthPrompter: Send {
+    /// Returns the requesting user's phone number
+    fn phone(&mut self) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    /// Returns the login code the requesting user received through [`SentCode::delivery`].
+    /// `attempt` is 1 the first time the code is asked for, and increases after every invalid
+    /// code
+    fn code(
+        &mut self,
+        sent_code: &SentCode,
+        attempt: u32,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    /// Returns whether the login code should be sent again after an invalid code (through
+    /// [`SentCode::next`] if telegram provided it), rather than asked for again. By default, the
+    /// code is never sent again
+    fn resend(
+        &mut self,
+        sent_code: &SentCode,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send {
+        let _ = sent_code;
+        ready(Ok(false))
+    }
+
+    /// Returns the password the requesting user set up, along with the `hint` they set up for it.
+    /// `attempt` is 1 the first time the password is asked for, and increases after every
+    /// invalid password
+    fn password(
+        &mut self,
+        hint: Option<&str>,
+        attempt: u32,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    /// Displays the [`QrLogin`] to scan. Called again with a new [`QrLogin`] every time the
+    /// previous one expires. By default, QR code login is not supported
+    fn qr_login(&mut self, qr_login: &QrLogin) -> impl Future<Output = anyhow::Result<()>> + Send {
+        let _ = qr_login;
+        ready(Err(anyhow::anyhow!("QR code login is not supported")))
+    }
+
+    /// Notifies the requesting user that `error` occurred while signing in, before the input
+    /// that caused it is asked for again. Does nothing by default
+    fn notify_error(&mut self, error: &Error) -> impl Future<Output = ()> + Send {
+        let _ = error;
+        ready(())
+    }
+}
+
+/// How a login code was sent to the requesting user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeDelivery {
+    /// As a message in the telegram app, on another device where the user is signed in
+    App,
+    /// By SMS
+    Sms,
+    /// By a phone call reading the code out
+    Call,
+    /// By a phone call from a number matching the code
+    FlashCall,
+    /// By a missed call from a number ending with the code
+    MissedCall,
+    /// By email
+    Email,
+    /// By SMS, to the anonymous number bought on fragment.com
+    FragmentSms,
+    /// By a method this crate does not know about
+    Other,
+}
+
+impl fmt::Display for CodeDelivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::App => "the telegram app",
+            Self::Sms => "SMS",
+            Self::Call => "a phone call",
+            Self::FlashCall => "a flash call",
+            Self::MissedCall => "a missed call",
+            Self::Email => "email",
+            Self::FragmentSms => "fragment.com SMS",
+            Self::Other => "another method",
+        })
+    }
+}
+
+impl From<tl::enums::auth::SentCodeType> for CodeDelivery {
+    fn from(value: tl::enums::auth::SentCodeType) -> Self {
+        use tl::enums::auth::SentCodeType;
+
+        match value {
+            SentCodeType::App(_) => Self::App,
+            SentCodeType::Sms(_) | SentCodeType::FirebaseSms(_) => Self::Sms,
+            SentCodeType::Call(_) => Self::Call,
+            SentCodeType::FlashCall(_) => Self::FlashCall,
+            SentCodeType::MissedCall(_) => Self::MissedCall,
+            SentCodeType::EmailCode(_) => Self::Email,
+            SentCodeType::FragmentSms(_) => Self::FragmentSms,
+            SentCodeType::SetUpEmailRequired(_) => Self::Other,
+        }
+    }
+}
+
+impl From<tl::enums::auth::CodeType> for CodeDelivery {
+    fn from(value: tl::enums::auth::CodeType) -> Self {
+        use tl::enums::auth::CodeType;
+
+        match value {
+            CodeType::Sms => Self::Sms,
+            CodeType::Call => Self::Call,
+            CodeType::FlashCall => Self::FlashCall,
+            CodeType::MissedCall => Self::MissedCall,
+            CodeType::FragmentSms => Self::FragmentSms,
+        }
+    }
+}
+
+/// A login code sent to the requesting user (see [`AuthPrompter::code`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SentCode {
+    /// How the code was sent
+    pub delivery: CodeDelivery,
+    /// How the code is sent if it is sent again (see [`AuthPrompter::resend`]), if telegram
+    /// provided it
+    pub next: Option<CodeDelivery>,
+}
+
+/// A login token to display as a QR code, to be scanned from a device where the requesting user
+/// is already signed in (see [`crate::UserRetriever::sign_in_qr`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QrLogin {
+    /// The `tg://login` URL to encode in the QR code
+    pub url: String,
+    /// When the login token expires. A new [`QrLogin`] is issued before then
+    pub expires: Timestamp,
+}
+
+impl QrLogin {
+    pub(crate) fn new(token: &[u8], expires: Timestamp) -> Self {
+        Self {
+            url: format!("tg://login?token={}", URL_SAFE_NO_PAD.encode(token)),
+            expires,
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{transport::Transport, Error, Result};
+use grammers_tl_types as tl;
+#[cfg(feature = "fake")]
+use std::sync::{Mutex, PoisonError};
+use std::{future::Future, time::Duration};
+use tl::{enums::InputContact, types::contacts::ImportedContacts};
+use tracing::warn;
+
+/// Default amount of phone numbers sent per `contacts.ImportContacts` request
+pub const DEFAULT_CHUNK_SIZE: usize = 50;
+/// Default delay between two `contacts.ImportContacts` requests
+pub const DEFAULT_BATCH_DELAY: Duration = Duration::from_secs(5);
+/// Default longest `FLOOD_WAIT` we are willing to sleep through before giving up
+pub const DEFAULT_MAX_FLOOD_WAIT: Duration = Duration::from_mins(15);
+
+/// Options controlling how phone numbers are split into `contacts.ImportContacts` requests
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// Amount of phone numbers sent per request
+    pub chunk_size: usize,
+    /// Delay between two requests
+    pub delay: Duration,
+    /// Longest `FLOOD_WAIT` we are willing to sleep through. [`None`] waits however long
+    /// telegram asks us to
+    pub max_flood_wait: Option<Duration>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            delay: DEFAULT_BATCH_DELAY,
+            max_flood_wait: Some(DEFAULT_MAX_FLOOD_WAIT),
+        }
+    }
+}
+
+/// Abstraction over waiting, so the batching logic can be driven without actually sleeping
+pub trait Clock {
+    /// Waits for `duration`
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send;
+}
+
+/// [`Clock`] backed by [`tokio::time::sleep`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep(duration)
+    }
+}
+
+/// [`Clock`] returning immediately and recording every wait, used to test the batching logic
+/// without sleeping
+#[cfg(feature = "fake")]
+#[derive(Debug, Default)]
+pub struct FakeClock {
+    sleeps: Mutex<Vec<Duration>>,
+}
+
+#[cfg(feature = "fake")]
+impl FakeClock {
+    /// Returns a new [`FakeClock`] that has not waited yet
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns every wait requested so far
+    #[must_use]
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(feature = "fake")]
+impl Clock for FakeClock {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        self.sleeps
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(duration);
+        std::future::ready(())
+    }
+}
+
+/// Imports `contacts` in a single `contacts.ImportContacts` request, retrying after sleeping
+/// through any `FLOOD_WAIT_X` (see [`Error::RateLimited`]) shorter than
+/// [`BatchOptions::max_flood_wait`]
+///
+/// # Errors
+///
+/// Returns [`Err`] if the request fails for any reason other than a flood wait, or
+/// [`Error::RateLimited`] if the flood wait is longer than [`BatchOptions::max_flood_wait`]
+pub async fn import_batch<T, C>(
+    transport: &T,
+    clock: &C,
+    options: &BatchOptions,
+    contacts: Vec<InputContact>,
+) -> Result<ImportedContacts>
+where
+    T: Transport,
+    C: Clock + Sync,
+{
+    retry_flood_wait(clock, options, || {
+        transport.import_contacts(contacts.clone())
+    })
+    .await
+}
+
+/// Sends `request` until it succeeds, sleeping through any `FLOOD_WAIT_X` (see
+/// [`Error::RateLimited`]) shorter than [`BatchOptions::max_flood_wait`]
+///
+/// # Note
+///
+/// [`grammers_client::Client`] already sleeps through short flood waits on its own (see
+/// [`grammers_client::InitParams::flood_sleep_threshold`]), only the longer ones reach us
+///
+/// # Errors
+///
+/// Returns [`Err`] if the request fails for any reason other than a flood wait, or
+/// [`Error::RateLimited`] if the flood wait is longer than [`BatchOptions::max_flood_wait`]
+pub async fn retry_flood_wait<C, F, Fut, R>(
+    clock: &C,
+    options: &BatchOptions,
+    mut request: F,
+) -> Result<R>
+where
+    C: Clock + Sync,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<R>>,
+{
+    loop {
+        match request().await {
+            Ok(response) => return Ok(response),
+            Err(Error::RateLimited(wait))
+                if options.max_flood_wait.is_none_or(|max| wait <= max) =>
+            {
+                warn!("Rate limited by telegram, waiting {wait:?} before retrying");
+                clock.sleep(wait).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{FakeCall, FakeTransport};
+    use std::collections::VecDeque;
+
+    /// Fake request answering with scripted responses in order
+    struct Scripted(Mutex<VecDeque<Result<u32>>>);
+
+    impl Scripted {
+        fn new(responses: Vec<Result<u32>>) -> Self {
+            Self(Mutex::new(responses.into()))
+        }
+
+        fn send(&self) -> std::future::Ready<Result<u32>> {
+            std::future::ready(self.0.lock().unwrap().pop_front().unwrap())
+        }
+
+        fn remaining(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+    }
+
+    #[tokio::test]
+    async fn sleeps_through_flood_waits_and_resumes() {
+        let clock = FakeClock::new();
+        let request = Scripted::new(vec![
+            Err(Error::RateLimited(Duration::from_secs(30))),
+            Err(Error::RateLimited(Duration::from_secs(10))),
+            Ok(7),
+        ]);
+
+        let response = retry_flood_wait(&clock, &BatchOptions::default(), || request.send())
+            .await
+            .unwrap();
+
+        assert_eq!(response, 7);
+        assert_eq!(
+            clock.sleeps(),
+            [Duration::from_secs(30), Duration::from_secs(10)]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_above_max_flood_wait() {
+        let clock = FakeClock::new();
+        let too_long = DEFAULT_MAX_FLOOD_WAIT + Duration::from_secs(1);
+        let request = Scripted::new(vec![
+            Err(Error::RateLimited(DEFAULT_MAX_FLOOD_WAIT)),
+            Err(Error::RateLimited(too_long)),
+        ]);
+
+        let response = retry_flood_wait(&clock, &BatchOptions::default(), || request.send()).await;
+
+        assert!(matches!(response, Err(Error::RateLimited(wait)) if wait == too_long));
+        assert_eq!(clock.sleeps(), [DEFAULT_MAX_FLOOD_WAIT]);
+    }
+
+    #[tokio::test]
+    async fn waits_however_long_without_max_flood_wait() {
+        let clock = FakeClock::new();
+        let options = BatchOptions {
+            max_flood_wait: None,
+            ..Default::default()
+        };
+        let request = Scripted::new(vec![
+            Err(Error::RateLimited(Duration::from_hours(24))),
+            Ok(1),
+        ]);
+
+        let response = retry_flood_wait(&clock, &options, || request.send()).await;
+
+        assert_eq!(response.unwrap(), 1);
+        assert_eq!(clock.sleeps(), [Duration::from_hours(24)]);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let clock = FakeClock::new();
+        let request = Scripted::new(vec![Err(Error::Validation("invalid".into())), Ok(1)]);
+
+        let response = retry_flood_wait(&clock, &BatchOptions::default(), || request.send()).await;
+
+        assert!(matches!(response, Err(Error::Validation(_))));
+        assert!(clock.sleeps().is_empty());
+        assert_eq!(request.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn import_batch_retries_after_flood_wait() {
+        let clock = FakeClock::new();
+        let transport = FakeTransport::new();
+        transport.push_import(Err(Error::RateLimited(Duration::from_secs(42))));
+        let contacts = vec![InputContact::InputPhoneContact(
+            tl::types::InputPhoneContact {
+                client_id: 0,
+                phone: "+14155552671".into(),
+                first_name: String::new(),
+                last_name: String::new(),
+            },
+        )];
+
+        import_batch(&transport, &clock, &BatchOptions::default(), contacts)
+            .await
+            .unwrap();
+
+        assert_eq!(clock.sleeps(), [Duration::from_secs(42)]);
+        let imports = transport
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, FakeCall::ImportContacts { .. }))
+            .count();
+        assert_eq!(imports, 2);
+    }
+}
@@ -0,0 +1,604 @@
+use crate::{
+    photos::DownloadedPhoto,
+    user::{FullUserData, Timestamp, UserData},
+    Error, Result,
+};
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use tl::{
+    enums::{Chat, Peer, User},
+    types::contacts::ResolvedPeer,
+};
+
+/// The version of the [`ResultFile`] format written by this crate
+pub const RESULT_FILE_VERSION: u32 = 2;
+
+/// The result of checking a single input phone number
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    /// The phone number exactly as it was provided
+    pub input: String,
+    /// The other provided phone numbers that normalize to the same value as `input`, and were
+    /// therefore only checked once
+    pub aliases: Vec<String>,
+    /// The provided phone number formatted as E.164, or [`None`] if it could not be parsed
+    pub normalized: Option<String>,
+    /// The `client_id` sent alongside the phone number when importing it as a contact
+    pub client_id: i64,
+    /// What was found for this phone number
+    pub outcome: CheckOutcome,
+    /// Whether the account found was already one of the requesting user's contacts before the
+    /// check. Such accounts are never removed when cleaning up (see
+    /// [`crate::UserRetriever::delete_contacts`]). Always `false` with
+    /// [`LookupStrategy::ResolvePhone`], which adds no contact
+    pub existing_contact: bool,
+    /// How the account was looked up from the phone number
+    #[serde(default)]
+    pub strategy: LookupStrategy,
+    /// When telegram answered for this phone number
+    #[serde(default)]
+    pub retrieved_at: Option<Timestamp>,
+    /// When the account found was last seen as of `retrieved_at` (see
+    /// [`crate::user::UserStatus::last_seen`])
+    #[serde(default)]
+    pub last_seen: Option<String>,
+    /// The full profile of the account found, if requested (see
+    /// [`crate::UserRetriever::with_full_users`])
+    #[serde(default)]
+    pub full_user: Option<FullUserData>,
+    /// The profile photos of the account found saved to the evidence directory, if requested
+    /// (see [`crate::UserRetriever::with_photos`])
+    #[serde(default)]
+    pub photos: Option<Vec<DownloadedPhoto>>,
+}
+
+impl CheckResult {
+    /// Returns the [`UserData`] associated with this phone number, if an account was found
+    #[must_use]
+    pub fn user(&self) -> Option<&UserData> {
+        match &self.outcome {
+            CheckOutcome::Found(user) => Some(user),
+            _ => None,
+        }
+    }
+}
+
+/// How accounts are looked up from phone numbers (see
+/// [`crate::UserRetriever::with_lookup_strategy`])
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LookupStrategy {
+    /// The phone numbers are added to the requesting user's contacts with
+    /// `contacts.ImportContacts` and removed afterwards. Many phone numbers are checked per
+    /// request, but the address book is modified and telegram strictly limits imports
+    #[default]
+    ImportContacts,
+    /// Every phone number is resolved with `contacts.ResolvePhone`, without modifying the
+    /// address book. A request is sent per phone number, waiting
+    /// [`crate::batch::BatchOptions::delay`] between them
+    ResolvePhone,
+}
+
+/// The outcome of checking a single input phone number
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckOutcome {
+    /// The phone number is associated with this account
+    Found(Box<UserData>),
+    /// No account was returned: either the phone number is not registered on telegram or the
+    /// account does not allow being found by its phone number
+    NotRegisteredOrHidden,
+    /// The phone number could not be parsed by [`phonenumber`]
+    InvalidNumber {
+        /// The [`phonenumber::ParseError`] message
+        error: String,
+    },
+    /// Telegram refused to import the phone number for now, it should be checked again later
+    RetryLater,
+}
+
+/// The result of resolving a single input username (see
+/// [`crate::UserRetriever::resolve_usernames`])
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsernameResult {
+    /// The username exactly as it was provided
+    pub input: String,
+    /// The username sent to telegram, without the leading `@` or `t.me/` link, or [`None`] if it
+    /// is not a valid username
+    pub username: Option<String>,
+    /// What was found for this username
+    pub outcome: UsernameOutcome,
+    /// When telegram answered for this username
+    #[serde(default)]
+    pub retrieved_at: Option<Timestamp>,
+}
+
+impl UsernameResult {
+    /// Returns the [`UserData`] associated with this username, if an account or bot was found
+    #[must_use]
+    pub fn user(&self) -> Option<&UserData> {
+        match &self.outcome {
+            UsernameOutcome::Found(user) | UsernameOutcome::Bot(user) => Some(user),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of resolving a single input username
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UsernameOutcome {
+    /// The username belongs to this account
+    Found(Box<UserData>),
+    /// The username belongs to this bot
+    Bot(Box<UserData>),
+    /// The username belongs to a channel or a group rather than an account
+    Channel {
+        /// The id of the channel or group
+        id: i64,
+        /// The title of the channel or group, if it was returned
+        title: Option<String>,
+    },
+    /// No account, channel or group uses this username
+    NotFound,
+    /// The username is malformed
+    InvalidUsername {
+        /// The reason the username is invalid
+        error: String,
+    },
+}
+
+impl From<ResolvedPeer> for UsernameOutcome {
+    fn from(value: ResolvedPeer) -> Self {
+        let channel_id = match value.peer {
+            Peer::User(peer) => {
+                return value
+                    .users
+                    .into_iter()
+                    .find_map(|user| match user {
+                        User::User(user) if user.id == peer.user_id => Some(UserData::from(user)),
+                        _ => None,
+                    })
+                    .map_or(Self::NotFound, |user| {
+                        if user.bot {
+                            Self::Bot(Box::new(user))
+                        } else {
+                            Self::Found(Box::new(user))
+                        }
+                    });
+            }
+            Peer::Chat(peer) => peer.chat_id,
+            Peer::Channel(peer) => peer.channel_id,
+        };
+        let title = value.chats.into_iter().find_map(|chat| match chat {
+            Chat::Chat(chat) if chat.id == channel_id => Some(chat.title),
+            Chat::Forbidden(chat) if chat.id == channel_id => Some(chat.title),
+            Chat::Channel(chat) if chat.id == channel_id => Some(chat.title),
+            Chat::ChannelForbidden(chat) if chat.id == channel_id => Some(chat.title),
+            _ => None,
+        });
+        Self::Channel {
+            id: channel_id,
+            title,
+        }
+    }
+}
+
+/// The file the results of a check are saved to, versioned so it can be read back by later
+/// releases (see [`RESULT_FILE_VERSION`])
+///
+/// # Examples
+///
+/// ```
+/// use telechecker_lib::{
+///     check::{CheckOutcome, CheckResult, LookupStrategy, ResultFile},
+///     user::*,
+/// };
+///
+/// let user = UserData {
+///     id: 42,
+///     first_name: Some("Jane".to_string()),
+///     photo: Some(UserProfilePhoto {
+///         has_video: false,
+///         personal: false,
+///         photo_id: 7,
+///         stripped_thumb: Some(vec![1, 40, 40]),
+///         dc_id: 4,
+///         thumbnail: None,
+///     }),
+///     status: Some(UserStatus::Offline(UserStatusOffline { was_online: 1_700_000_000.into() })),
+///     restriction_reason: Some(vec![RestrictionReason {
+///         platform: "ios".to_string(),
+///         reason: "porn".to_string(),
+///         text: "Unavailable".to_string(),
+///     }]),
+///     emoji_status: Some(EmojiStatus::Until(EmojiStatusUntil { document_id: 9, until: 1.into() })),
+///     usernames: Some(vec![Username {
+///         editable: true,
+///         active: true,
+///         username: "jane".to_string(),
+///     }]),
+///     ..Default::default()
+/// };
+/// let result = |input: &str, outcome| CheckResult {
+///     input: input.to_string(),
+///     aliases: Vec::new(),
+///     normalized: None,
+///     client_id: 0,
+///     outcome,
+///     existing_contact: false,
+///     strategy: LookupStrategy::ImportContacts,
+///     retrieved_at: Some(Timestamp::from_unix(1_700_003_600)),
+///     last_seen: None,
+///     full_user: None,
+///     photos: None,
+/// };
+/// let file = ResultFile::new(vec![
+///     result("+1 404 123 4567", CheckOutcome::Found(Box::new(user))),
+///     result("+1 404 123 4568", CheckOutcome::NotRegisteredOrHidden),
+///     result("+1 404 123 4569", CheckOutcome::RetryLater),
+///     result("404", CheckOutcome::InvalidNumber { error: "too short".to_string() }),
+/// ]);
+///
+/// let mut buf = Vec::new();
+/// file.write(&mut buf)?;
+/// assert_eq!(ResultFile::read(buf.as_slice())?, file);
+/// # Ok::<(), telechecker_lib::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultFile {
+    /// The version of the format the file was written with
+    pub version: u32,
+    /// A [`CheckResult`] per checked phone number
+    pub results: Vec<CheckResult>,
+    /// A [`UsernameResult`] per resolved username
+    #[serde(default)]
+    pub usernames: Vec<UsernameResult>,
+}
+
+/// Every format a [`ResultFile`] can be read from
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyResultFile {
+    Versioned(ResultFile),
+    /// Files written before the format was versioned, holding only the results
+    Unversioned(Vec<CheckResult>),
+}
+
+impl ResultFile {
+    /// Returns a new [`ResultFile`] holding `results`, using the latest format
+    #[must_use]
+    pub const fn new(results: Vec<CheckResult>) -> Self {
+        Self {
+            version: RESULT_FILE_VERSION,
+            results,
+            usernames: Vec::new(),
+        }
+    }
+
+    /// Reads a [`ResultFile`] from `reader`, accepting every version up to
+    /// [`RESULT_FILE_VERSION`] as well as unversioned files (a bare list of results)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResultFormat`] if `reader` does not hold a valid result file, or
+    /// [`Error::UnsupportedResultVersion`] if it was written by a later release
+    pub fn read(reader: impl Read) -> Result<Self> {
+        match serde_json::from_reader(reader).map_err(Error::ResultFormat)? {
+            AnyResultFile::Versioned(file) if file.version > RESULT_FILE_VERSION => {
+                Err(Error::UnsupportedResultVersion(file.version))
+            }
+            AnyResultFile::Versioned(file) => Ok(file),
+            AnyResultFile::Unversioned(results) => Ok(Self::new(results)),
+        }
+    }
+
+    /// Writes the [`ResultFile`] to `writer` as pretty-printed JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResultFormat`] if `writer` fails
+    pub fn write(&self, writer: impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(Error::ResultFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::*;
+    use serde_json::{json, Value};
+
+    fn user(id: i64, status: UserStatus, emoji_status: Option<EmojiStatus>) -> UserData {
+        UserData {
+            id,
+            access_hash: Some(id * 2),
+            first_name: Some("Jane".to_string()),
+            phone: Some("14155552671".to_string()),
+            photo: Some(UserProfilePhoto {
+                has_video: true,
+                personal: false,
+                photo_id: 7,
+                stripped_thumb: Some(vec![1, 40, 40, 0xff]),
+                dc_id: 4,
+                thumbnail: Some("data:image/jpeg;base64,/9j/".to_string()),
+            }),
+            status: Some(status),
+            restriction_reason: Some(vec![RestrictionReason {
+                platform: "ios".to_string(),
+                reason: "porn".to_string(),
+                text: "Unavailable".to_string(),
+            }]),
+            emoji_status,
+            usernames: Some(vec![Username {
+                editable: true,
+                active: false,
+                username: "jane".to_string(),
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn full_user(id: i64) -> FullUserData {
+        FullUserData {
+            id,
+            about: Some("bio".to_string()),
+            profile_photo_id: Some(7),
+            common_chats_count: 3,
+            premium_gifts: Some(vec![PremiumGiftOption {
+                months: 3,
+                currency: "EUR".to_string(),
+                amount: 1299,
+                bot_url: "https://t.me/premiumbot".to_string(),
+                store_product: None,
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn result(input: &str, outcome: CheckOutcome) -> CheckResult {
+        CheckResult {
+            input: input.to_string(),
+            aliases: Vec::new(),
+            normalized: None,
+            client_id: 0,
+            outcome,
+            existing_contact: false,
+            strategy: LookupStrategy::ImportContacts,
+            retrieved_at: Some(Timestamp::from_unix(1_700_003_600)),
+            last_seen: None,
+            full_user: None,
+            photos: None,
+        }
+    }
+
+    fn username(input: &str, outcome: UsernameOutcome) -> UsernameResult {
+        UsernameResult {
+            input: input.to_string(),
+            username: Some(input.trim_start_matches('@').to_string()),
+            outcome,
+            retrieved_at: Some(Timestamp::from_unix(1_700_003_600)),
+        }
+    }
+
+    /// Returns a [`ResultFile`] using every model type and variant
+    fn result_file() -> ResultFile {
+        let found = user(
+            42,
+            UserStatus::Offline(UserStatusOffline {
+                was_online: Timestamp::from_unix(1_700_000_000),
+            }),
+            Some(EmojiStatus::Until(EmojiStatusUntil {
+                document_id: 9,
+                until: Timestamp::from_unix(1_800_000_000),
+            })),
+        );
+        let mut results = vec![
+            CheckResult {
+                aliases: vec!["+1 (415) 555-2671".to_string()],
+                normalized: Some("+14155552671".to_string()),
+                existing_contact: true,
+                strategy: LookupStrategy::ResolvePhone,
+                last_seen: Some("an hour ago".to_string()),
+                full_user: Some(full_user(42)),
+                photos: Some(vec![DownloadedPhoto {
+                    photo_id: 7,
+                    date: Some(Timestamp::from_unix(1_600_000_000)),
+                    current: true,
+                    path: "evidence/run-1/42-7.jpg".into(),
+                    sha256: "ab".repeat(32),
+                }]),
+                ..result("+14155552671", CheckOutcome::Found(Box::new(found)))
+            },
+            result("+14155552672", CheckOutcome::NotRegisteredOrHidden),
+            result("+14155552673", CheckOutcome::RetryLater),
+            result(
+                "404",
+                CheckOutcome::InvalidNumber {
+                    error: "too short".to_string(),
+                },
+            ),
+        ];
+        for (i, status) in [
+            UserStatus::Empty,
+            UserStatus::Online(UserStatusOnline {
+                expires: Timestamp::from_unix(1_700_003_700),
+            }),
+            UserStatus::Recently,
+            UserStatus::LastWeek,
+            UserStatus::LastMonth,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = i64::try_from(i).unwrap();
+            let emoji_status = Some(EmojiStatus::Status(Status { document_id: id }));
+            let found = user(id, status, emoji_status);
+            results.push(result("+14155552670", CheckOutcome::Found(Box::new(found))));
+        }
+        let bot = UserData {
+            bot: true,
+            ..user(43, UserStatus::Empty, None)
+        };
+        ResultFile {
+            usernames: vec![
+                username(
+                    "@jane",
+                    UsernameOutcome::Found(Box::new(user(44, UserStatus::Recently, None))),
+                ),
+                username("@janebot", UsernameOutcome::Bot(Box::new(bot))),
+                username(
+                    "@channel",
+                    UsernameOutcome::Channel {
+                        id: 1,
+                        title: Some("Channel".to_string()),
+                    },
+                ),
+                username("@nobody", UsernameOutcome::NotFound),
+                UsernameResult {
+                    input: "@a".to_string(),
+                    username: None,
+                    outcome: UsernameOutcome::InvalidUsername {
+                        error: "usernames are 4 to 32 characters long".to_string(),
+                    },
+                    retrieved_at: None,
+                },
+            ],
+            ..ResultFile::new(results)
+        }
+    }
+
+    fn write(file: &ResultFile) -> Value {
+        let mut buf = Vec::new();
+        file.write(&mut buf).unwrap();
+        serde_json::from_slice(&buf).unwrap()
+    }
+
+    fn read(value: &Value) -> Result<ResultFile> {
+        ResultFile::read(value.to_string().as_bytes())
+    }
+
+    #[test]
+    fn round_trips_every_model_type() {
+        let file = result_file();
+        assert_eq!(read(&write(&file)).unwrap(), file);
+    }
+
+    #[cfg(not(feature = "raw-timestamps"))]
+    #[test]
+    fn timestamps_are_written_as_rfc3339() {
+        let value = write(&result_file());
+        assert_eq!(
+            value["results"][0]["retrieved_at"],
+            json!("2023-11-14T23:13:20Z")
+        );
+    }
+
+    #[cfg(feature = "raw-timestamps")]
+    #[test]
+    fn raw_timestamps_are_written_with_unix_seconds() {
+        let value = write(&result_file());
+        assert_eq!(
+            value["results"][0]["retrieved_at"],
+            json!({ "unix": 1_700_003_600, "rfc3339": "2023-11-14T23:13:20Z" })
+        );
+    }
+
+    #[test]
+    fn timestamps_are_read_from_every_format() {
+        for timestamp in [
+            json!(1_700_003_600),
+            json!("2023-11-14T23:13:20Z"),
+            json!("2023-11-15T00:13:20+01:00"),
+            json!({ "unix": 1_700_003_600, "rfc3339": "2023-11-14T23:13:20Z" }),
+        ] {
+            let timestamp: Timestamp = serde_json::from_value(timestamp).unwrap();
+            assert_eq!(timestamp, Timestamp::from_unix(1_700_003_600));
+        }
+    }
+
+    /// Returns `value` as written by version 1: timestamps as unix seconds, and none of the
+    /// fields added since
+    fn downgrade(value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for field in [
+                    "strategy",
+                    "retrieved_at",
+                    "last_seen",
+                    "full_user",
+                    "photos",
+                ] {
+                    object.remove(field);
+                }
+                object.remove("thumbnail");
+                for (field, value) in object.iter_mut() {
+                    if ["was_online", "expires", "until"].contains(&field.as_str()) {
+                        let timestamp: Timestamp = serde_json::from_value(value.clone()).unwrap();
+                        *value = json!(timestamp.unix());
+                    } else {
+                        downgrade(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(downgrade),
+            _ => {}
+        }
+    }
+
+    /// Returns `file` as read back from version 1, which lacks the fields added since
+    fn without_new_fields(mut file: ResultFile) -> ResultFile {
+        for result in &mut file.results {
+            result.strategy = LookupStrategy::default();
+            result.retrieved_at = None;
+            result.last_seen = None;
+            result.full_user = None;
+            result.photos = None;
+            if let CheckOutcome::Found(user) = &mut result.outcome {
+                if let Some(photo) = &mut user.photo {
+                    photo.thumbnail = None;
+                }
+            }
+        }
+        file.usernames = Vec::new();
+        file
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let mut value = write(&result_file());
+        value.as_object_mut().unwrap().remove("usernames");
+        downgrade(&mut value);
+        value["version"] = json!(1);
+
+        let file = read(&value).unwrap();
+
+        assert_eq!(file.version, 1);
+        assert_eq!(
+            file,
+            ResultFile {
+                version: 1,
+                ..without_new_fields(result_file())
+            }
+        );
+    }
+
+    #[test]
+    fn reads_unversioned_results() {
+        let mut value = write(&result_file())["results"].take();
+        downgrade(&mut value);
+
+        let file = read(&value).unwrap();
+
+        assert_eq!(file, without_new_fields(result_file()));
+    }
+
+    #[test]
+    fn rejects_later_versions() {
+        let mut value = write(&result_file());
+        value["version"] = json!(RESULT_FILE_VERSION + 1);
+
+        assert!(matches!(
+            read(&value),
+            Err(Error::UnsupportedResultVersion(version)) if version == RESULT_FILE_VERSION + 1
+        ));
+    }
+}
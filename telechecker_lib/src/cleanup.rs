@@ -0,0 +1,528 @@
+use crate::{
+    batch::{Clock, TokioClock},
+    check::CheckResult,
+    transport::Transport,
+    Error, Result, UserRetriever,
+};
+use futures_util::{Stream, TryStreamExt};
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tl::enums::{InputUser, User};
+use tracing::{debug, warn};
+
+/// Summary of the contacts removed after a check
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CleanupReport {
+    /// Ids of the accounts that were added by the check and have been removed
+    pub deleted: Vec<i64>,
+    /// Ids of the accounts that were already contacts before the check and have been kept
+    pub skipped: Vec<i64>,
+}
+
+/// An account added to the requesting user's contacts by a check
+#[derive(Serialize, Deserialize)]
+struct AddedContact {
+    user_id: i64,
+    access_hash: Option<i64>,
+}
+
+impl From<&AddedContact> for InputUser {
+    fn from(value: &AddedContact) -> Self {
+        Self::User(tl::types::InputUser {
+            user_id: value.user_id,
+            access_hash: value.access_hash.unwrap_or(0),
+        })
+    }
+}
+
+/// On-disk record of the changes made to the requesting user's contacts, written before and after
+/// every `contacts.ImportContacts` so an interrupted run can be cleaned up later
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    /// E.164 phone numbers sent to telegram whose resulting accounts have not been recorded yet
+    pending: Vec<String>,
+    /// Accounts added to the requesting user's contacts
+    added: Vec<AddedContact>,
+    /// Accounts that were contacts before the check and must never be removed
+    existing: Vec<i64>,
+}
+
+impl Journal {
+    /// Loads the journal at `path`, returning [`None`] if there is none
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        let buf = match fs::read_to_string(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Journal(e)),
+        };
+        let mut journal: Self = serde_json::from_str(&buf).map_err(Error::JournalFormat)?;
+        journal.path = path.to_path_buf();
+        Ok(Some(journal))
+    }
+
+    /// Removes the journal at `path`, if any
+    pub(crate) fn remove(path: &Path) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::Journal(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the journal to disk, replacing the previous one atomically
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let buf = serde_json::to_string(self).map_err(Error::JournalFormat)?;
+        fs::write(&tmp, buf).map_err(Error::Journal)?;
+        fs::rename(&tmp, &self.path).map_err(Error::Journal)?;
+        Ok(())
+    }
+
+    /// Records the phone numbers about to be imported, and the contacts that existed beforehand
+    pub(crate) fn record_pending(
+        &mut self,
+        phones: impl Iterator<Item = String>,
+        existing: &HashSet<i64>,
+    ) -> Result<()> {
+        self.pending.extend(phones);
+        self.existing.extend(existing);
+        self.existing.sort_unstable();
+        self.existing.dedup();
+        self.save()
+    }
+
+    /// Records the accounts added to the requesting user's contacts by the import of `results`,
+    /// which are no longer pending
+    pub(crate) fn record_added(&mut self, results: &[CheckResult]) -> Result<()> {
+        self.added.extend(
+            results
+                .iter()
+                .filter(|result| !result.existing_contact)
+                .filter_map(CheckResult::user)
+                .map(|user| AddedContact {
+                    user_id: user.id,
+                    access_hash: user.access_hash,
+                }),
+        );
+        let imported: HashSet<&str> = results
+            .iter()
+            .filter_map(|result| result.normalized.as_deref())
+            .collect();
+        self.pending
+            .retain(|phone| !imported.contains(phone.as_str()));
+        self.save()
+    }
+
+    /// Whether nothing has been recorded
+    const fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.added.is_empty()
+    }
+
+    /// Removes every recorded account from the requesting user's contacts
+    ///
+    /// Phone numbers that were imported without their accounts being recorded are matched
+    /// against the phone numbers of the requesting user's current contacts
+    pub(crate) async fn cleanup<T: Transport, C: Clock + Sync>(
+        &self,
+        retriever: &UserRetriever<'_, T, C>,
+    ) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+        let mut delete_users: Vec<InputUser> = self.added.iter().map(InputUser::from).collect();
+        report.deleted.extend(self.added.iter().map(|c| c.user_id));
+
+        if !self.pending.is_empty() {
+            debug!("Matching {} pending phone numbers", self.pending.len());
+            let pending: HashSet<&str> = self
+                .pending
+                .iter()
+                .map(|phone| phone.trim_start_matches('+'))
+                .collect();
+            let contacts = retriever.contacts().await?;
+            for user in contacts.users {
+                let User::User(user) = user else {
+                    continue;
+                };
+                if !user
+                    .phone
+                    .as_deref()
+                    .is_some_and(|phone| pending.contains(phone))
+                    || report.deleted.contains(&user.id)
+                {
+                    continue;
+                }
+                if self.existing.contains(&user.id) {
+                    report.skipped.push(user.id);
+                } else {
+                    delete_users.push(InputUser::User(tl::types::InputUser {
+                        user_id: user.id,
+                        access_hash: user.access_hash.unwrap_or(0),
+                    }));
+                    report.deleted.push(user.id);
+                }
+            }
+        }
+
+        retriever.delete_input_users(delete_users).await?;
+        Ok(report)
+    }
+}
+
+/// A scope in which phone numbers are imported into the requesting user's contacts, only
+/// available within [`UserRetriever::with_import_session`]
+///
+/// Every import is recorded to an on-disk journal before `contacts.ImportContacts` is sent, and
+/// the accounts added are removed when the scope ends, however it ends. If the process panics or
+/// is killed first, the journal is left behind and [`UserRetriever::recover_cleanup`] finishes the
+/// cleanup on the next startup
+pub struct ImportSession<'r, 'a, T: Transport = Client, C: Clock = TokioClock> {
+    retriever: &'r UserRetriever<'a, T, C>,
+    journal: Journal,
+    skipped: Vec<i64>,
+    keep: bool,
+    finished: bool,
+}
+
+impl<'r, 'a, T: Transport, C: Clock + Sync> ImportSession<'r, 'a, T, C> {
+    /// Returns a new [`ImportSession`] journaled to `path`, which must not hold an unfinished
+    /// cleanup
+    pub(crate) fn new(retriever: &'r UserRetriever<'a, T, C>, path: PathBuf) -> Self {
+        Self {
+            retriever,
+            journal: Journal {
+                path,
+                ..Default::default()
+            },
+            skipped: Vec::new(),
+            keep: false,
+            finished: false,
+        }
+    }
+
+    /// Equivalent to [`UserRetriever::get_users`], journaling the changes made to the requesting
+    /// user's contacts
+    ///
+    /// # Errors
+    ///
+    /// Will return [Err] if the journal cannot be written, or for any reason
+    /// [`UserRetriever::get_users`] would
+    pub async fn get_users(&mut self, numbers: Vec<String>) -> Result<Vec<CheckResult>> {
+        self.get_users_stream(numbers).try_collect().await
+    }
+
+    /// Equivalent to [`UserRetriever::get_users_stream`], journaling the changes made to the
+    /// requesting user's contacts as each batch is imported
+    pub fn get_users_stream(
+        &mut self,
+        numbers: Vec<String>,
+    ) -> impl Stream<Item = Result<CheckResult>> + Send + '_ {
+        let skipped = &mut self.skipped;
+        self.retriever
+            .check_stream(numbers, Some(&mut self.journal))
+            .inspect_ok(move |result| {
+                if let Some(user) = result.user().filter(|_| result.existing_contact) {
+                    skipped.push(user.id);
+                }
+            })
+    }
+
+    /// Keeps the accounts added during this session as contacts when it ends, rather than
+    /// removing them
+    pub const fn keep_contacts(&mut self) {
+        self.keep = true;
+    }
+
+    /// Removes every account added during this session from the requesting user's contacts,
+    /// unless they are kept (see [`ImportSession::keep_contacts`]), and removes the journal.
+    /// Returns [`None`] if the accounts are kept
+    ///
+    /// The journal is kept if this fails (see [`UserRetriever::recover_cleanup`])
+    pub(crate) async fn finish(mut self) -> Result<Option<CleanupReport>> {
+        let report = if self.keep {
+            None
+        } else {
+            let mut report = self.journal.cleanup(self.retriever).await?;
+            report.skipped.append(&mut self.skipped);
+            Some(report)
+        };
+        Journal::remove(&self.journal.path)?;
+        self.finished = true;
+        Ok(report)
+    }
+}
+
+impl<T: Transport, C: Clock> Drop for ImportSession<'_, '_, T, C> {
+    fn drop(&mut self) {
+        if !self.finished && !self.journal.is_empty() {
+            warn!(
+                "Import session interrupted before cleanup, it will be finished on next startup from '{}'",
+                self.journal.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        check::{CheckOutcome, LookupStrategy},
+        session::MemorySessionStore,
+        transport::FakeTransport,
+        JOURNAL_FILE,
+    };
+    use tl::types::contacts::ImportedContacts;
+
+    fn imported(user: tl::types::User) -> ImportedContacts {
+        ImportedContacts {
+            imported: vec![tl::types::ImportedContact {
+                user_id: user.id,
+                client_id: 0,
+            }
+            .into()],
+            popular_invites: Vec::new(),
+            retry_contacts: Vec::new(),
+            users: vec![User::User(user)],
+        }
+    }
+
+    #[tokio::test]
+    async fn session_removes_added_contacts_and_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let transport = FakeTransport::new().authorized(true);
+        transport.push_import(Ok(imported(FakeTransport::user(
+            10,
+            Some("14155552671".into()),
+        ))));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let (checked, report) = retriever
+            .with_import_session(&journal_file, async |session| {
+                let checked = session.get_users(vec!["+14155552671".into()]).await;
+                assert!(journal_file.exists());
+                checked
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(checked.unwrap().len(), 1);
+        assert_eq!(report.unwrap().deleted, [10]);
+        assert!(!journal_file.exists());
+        assert!(retriever.into_inner().contact_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_removes_added_contacts_when_the_check_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let existing = FakeTransport::user(20, Some("14155552672".into()));
+        let transport = FakeTransport::new().authorized(true).with_contact(existing);
+        transport.push_lost_import(imported(FakeTransport::user(
+            10,
+            Some("14155552671".into()),
+        )));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let (checked, report) = retriever
+            .with_import_session(&journal_file, async |session| {
+                session
+                    .get_users(vec!["+14155552671".into(), "+14155552672".into()])
+                    .await
+            })
+            .await
+            .unwrap();
+
+        assert!(checked.is_err());
+        assert_eq!(
+            report,
+            Some(CleanupReport {
+                deleted: vec![10],
+                skipped: vec![20],
+            })
+        );
+        assert!(!journal_file.exists());
+        assert_eq!(retriever.into_inner().contact_ids(), [20]);
+    }
+
+    #[tokio::test]
+    async fn session_keeps_contacts_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let transport = FakeTransport::new().authorized(true);
+        transport.push_import(Ok(imported(FakeTransport::user(
+            10,
+            Some("14155552671".into()),
+        ))));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let (_, report) = retriever
+            .with_import_session(&journal_file, async |session| {
+                session.keep_contacts();
+                session.get_users(vec!["+14155552671".into()]).await
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(report, None);
+        assert!(!journal_file.exists());
+        assert_eq!(retriever.into_inner().contact_ids(), [10]);
+    }
+
+    #[tokio::test]
+    async fn session_removes_contacts_left_by_a_crashed_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let existing = FakeTransport::user(20, Some("14155552672".into()));
+        let transport = FakeTransport::new().authorized(true).with_contact(existing);
+        // the process is killed while the first batch is imported
+        transport.push_lost_import(imported(FakeTransport::user(
+            10,
+            Some("14155552671".into()),
+        )));
+        transport.push_import(Ok(imported(FakeTransport::user(
+            11,
+            Some("14155552673".into()),
+        ))));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+        let mut crashed = ImportSession::new(&retriever, journal_file.clone());
+        assert!(crashed
+            .get_users(vec!["+14155552671".into()])
+            .await
+            .is_err());
+        std::mem::forget(crashed);
+
+        let (checked, report) = retriever
+            .with_import_session(&journal_file, async |session| {
+                session.get_users(vec!["+14155552673".into()]).await
+            })
+            .await
+            .unwrap();
+
+        let checked = checked.unwrap();
+        assert!(!checked[0].existing_contact);
+        assert_eq!(report.unwrap().deleted, [11]);
+        assert!(!journal_file.exists());
+        assert_eq!(retriever.into_inner().contact_ids(), [20]);
+    }
+
+    #[test]
+    fn record_added_keeps_the_other_pending_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal {
+            path: dir.path().join(JOURNAL_FILE),
+            ..Default::default()
+        };
+        journal
+            .record_pending(
+                ["+14155552671".to_string(), "+14155552672".to_string()].into_iter(),
+                &HashSet::new(),
+            )
+            .unwrap();
+        let result = CheckResult {
+            input: "+14155552672".into(),
+            aliases: Vec::new(),
+            normalized: Some("+14155552672".into()),
+            client_id: 1,
+            outcome: CheckOutcome::NotRegisteredOrHidden,
+            existing_contact: false,
+            strategy: LookupStrategy::ImportContacts,
+            retrieved_at: None,
+            last_seen: None,
+            full_user: None,
+            photos: None,
+        };
+
+        journal.record_added(&[result]).unwrap();
+
+        assert_eq!(journal.pending, ["+14155552671"]);
+        let saved = Journal::load(&journal.path).unwrap().unwrap();
+        assert_eq!(saved.pending, ["+14155552671"]);
+    }
+
+    #[tokio::test]
+    async fn recover_cleanup_removes_recorded_contacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let existing = FakeTransport::user(20, Some("14155552672".into()));
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_contact(existing.clone());
+        transport.push_import(Ok(imported(FakeTransport::user(
+            10,
+            Some("14155552671".into()),
+        ))));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let mut session = ImportSession::new(&retriever, journal_file.clone());
+        session
+            .get_users(vec!["+14155552671".into()])
+            .await
+            .unwrap();
+        // the process is killed before the session is finished
+        std::mem::forget(session);
+        assert_eq!(retriever.transport.contact_ids(), [20, 10]);
+
+        let report = retriever.recover_cleanup(&journal_file).await.unwrap();
+
+        assert_eq!(
+            report,
+            Some(CleanupReport {
+                deleted: vec![10],
+                skipped: Vec::new(),
+            })
+        );
+        assert!(!journal_file.exists());
+        assert_eq!(retriever.transport.contact_ids(), [20]);
+        assert_eq!(
+            retriever.recover_cleanup(&journal_file).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn recover_cleanup_matches_pending_phone_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_file = dir.path().join(JOURNAL_FILE);
+        let existing = FakeTransport::user(20, Some("14155552672".into()));
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_contact(existing.clone());
+        // the import reaches telegram, but its response is lost
+        transport.push_import(Err(Error::UnexpectedResponse("connection lost")));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+        let mut session = ImportSession::new(&retriever, journal_file.clone());
+        let checked = session
+            .get_users(vec!["+14155552671".into(), "+14155552672".into()])
+            .await;
+        assert!(checked.is_err());
+        // the process is killed before the session is finished
+        std::mem::forget(session);
+        assert!(journal_file.exists());
+
+        // on the next startup, the imported account is in the address book
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_contact(existing)
+            .with_contact(FakeTransport::user(10, Some("14155552671".into())));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+        let report = retriever.recover_cleanup(&journal_file).await.unwrap();
+
+        assert_eq!(
+            report,
+            Some(CleanupReport {
+                deleted: vec![10],
+                skipped: vec![20],
+            })
+        );
+        assert!(!journal_file.exists());
+        assert_eq!(retriever.into_inner().contact_ids(), [20]);
+    }
+}
@@ -0,0 +1,151 @@
+use grammers_mtsender::{AuthorizationError, InvocationError};
+use std::{io, time::Duration};
+
+/// Alias for a [`std::result::Result`] with the error type [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The error type returned by this crate
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Signing in failed
+    #[error("unable to sign in: {0}")]
+    SignIn(SignInFailure),
+
+    /// The session is not (or no longer) authorized, the user must sign in again
+    #[error("the session is not authorized: {0}")]
+    Unauthorized(String),
+
+    /// Telegram rejected the provided `api_id` or `api_hash`
+    #[error("invalid api_id or api_hash: {0}")]
+    InvalidApiCredentials(String),
+
+    /// Telegram rate limited us for longer than we are willing to wait
+    #[error("rate limited by telegram, retry in {0:?}")]
+    RateLimited(Duration),
+
+    /// Telegram answered a request with any other error
+    #[error("telegram returned error {code}: {name}")]
+    Rpc {
+        /// A numerical value similar to HTTP status codes
+        code: i32,
+        /// The error name, i.e. `PHONE_NUMBER_INVALID`
+        name: String,
+        /// The additional value of the error, if any (i.e. `X` in `FLOOD_WAIT_X`)
+        value: Option<u32>,
+    },
+
+    /// The connection to telegram could not be established
+    #[error("unable to connect to telegram: {0}")]
+    Connection(#[source] AuthorizationError),
+
+    /// The connection to telegram dropped or returned garbage
+    #[error("network error: {0}")]
+    Network(#[source] InvocationError),
+
+    /// A phone number could not be parsed
+    #[error("invalid phone number '{input}': {source}")]
+    InvalidPhone {
+        /// The phone number as it was provided
+        input: String,
+        /// The reason the phone number could not be parsed
+        source: phonenumber::ParseError,
+    },
+
+    /// The provided input is invalid
+    #[error("invalid input: {0}")]
+    Validation(String),
+
+    /// The username or password of a [`crate::builder::Proxy`] contains a character grammers
+    /// would send to the proxy percent-encoded, so the proxy would reject them
+    #[error("the proxy username or password contains '{0}', which cannot be sent to the proxy")]
+    UnsupportedProxyCredentials(char),
+
+    /// The [`crate::auth::AuthPrompter`] provided to [`crate::UserRetriever::sign_in`] failed
+    #[error("unable to retrieve input: {0}")]
+    Input(#[source] anyhow::Error),
+
+    /// The session could not be loaded or saved
+    #[error("session I/O failed: {0}")]
+    Session(#[source] io::Error),
+
+    /// The session could not be decrypted (see [`crate::session::EncryptedSessionStore`])
+    #[error("unable to decrypt the session: wrong passphrase or corrupted session")]
+    SessionEncryption,
+
+    /// An [`crate::session::EncryptedSessionStore`] found a session that is not encrypted, which
+    /// must be migrated first (see [`crate::session::EncryptedSessionStore::migrate`])
+    #[error("the session is not encrypted, it must be migrated first")]
+    UnencryptedSession,
+
+    /// The cleanup journal could not be read or written
+    #[error("cleanup journal I/O failed: {0}")]
+    Journal(#[source] io::Error),
+
+    /// The cleanup journal could not be (de)serialized
+    #[error("cleanup journal is malformed: {0}")]
+    JournalFormat(#[source] serde_json::Error),
+
+    /// A downloaded photo could not be saved to the evidence directory
+    #[error("evidence I/O failed: {0}")]
+    Evidence(#[source] io::Error),
+
+    /// A result file could not be (de)serialized (see [`crate::check::ResultFile`])
+    #[error("result file is malformed: {0}")]
+    ResultFormat(#[source] serde_json::Error),
+
+    /// A result file was written by a later release using an unknown format version
+    #[error("unsupported result file version {0}")]
+    UnsupportedResultVersion(u32),
+
+    /// Telegram answered with a response we did not expect
+    #[error("unexpected response from telegram: {0}")]
+    UnexpectedResponse(&'static str),
+}
+
+/// The reasons signing in can fail
+#[derive(Debug, thiserror::Error)]
+pub enum SignInFailure {
+    /// The phone number is not associated with a telegram account
+    #[error("sign up required")]
+    SignUpRequired,
+    /// The code provided is invalid
+    #[error("invalid code")]
+    InvalidCode,
+    /// The code provided expired, a new one must be requested
+    #[error("the code expired")]
+    CodeExpired,
+    /// The password provided is invalid
+    #[error("invalid password")]
+    InvalidPassword,
+}
+
+impl From<InvocationError> for Error {
+    fn from(value: InvocationError) -> Self {
+        let InvocationError::Rpc(rpc) = value else {
+            return Self::Network(value);
+        };
+        match (rpc.code, rpc.name.as_str()) {
+            (_, "FLOOD_WAIT") => {
+                Self::RateLimited(Duration::from_secs(rpc.value.map_or(0, u64::from)))
+            }
+            (_, "API_ID_INVALID" | "API_ID_PUBLISHED_FLOOD") => {
+                Self::InvalidApiCredentials(rpc.name)
+            }
+            (401, _) => Self::Unauthorized(rpc.name),
+            _ => Self::Rpc {
+                code: rpc.code,
+                name: rpc.name,
+                value: rpc.value,
+            },
+        }
+    }
+}
+
+impl From<AuthorizationError> for Error {
+    fn from(value: AuthorizationError) -> Self {
+        match value {
+            AuthorizationError::Invoke(e) => e.into(),
+            AuthorizationError::Gen(_) => Self::Connection(value),
+        }
+    }
+}
@@ -16,22 +16,86 @@
 //!
 //!
 
-use crate::user::UserData;
-use anyhow::{Context, Result};
-use grammers_client::{Client, Config, SignInError};
-use grammers_session::Session;
+use crate::{
+    auth::{AuthPrompter, QrLogin},
+    batch::{import_batch, retry_flood_wait, BatchOptions, Clock, TokioClock},
+    builder::UserRetrieverBuilder,
+    check::{CheckOutcome, CheckResult, LookupStrategy, UsernameOutcome, UsernameResult},
+    cleanup::{CleanupReport, ImportSession, Journal},
+    error::SignInFailure,
+    photos::{create_run_dir, download_photos, PhotoOptions},
+    session::{FileSessionStore, SessionStore},
+    transport::{ConnectParams, QrLoginStep, SignInStep, Transport},
+    user::{FullUserData, Timestamp, UserData},
+};
+use futures_util::{stream, Stream, TryStreamExt};
+use grammers_client::Client;
 use grammers_tl_types as tl;
 use grammers_tl_types::{
-    enums::{contacts::ImportedContacts, InputContact},
+    enums::{ImportedContact, InputContact},
     types::InputPhoneContact,
 };
-use std::future::Future;
+use phonenumber::{country, Mode};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tl::enums::{InputUser, User};
-use tracing::{debug, trace, warn};
+use tracing::{debug, warn};
 
 /// The name of the session file created by [`grammers_session::Session`]
 pub const SESSION_FILE: &str = "telechecker.session";
 
+/// The name of the journal file written by [`ImportSession`]
+pub const JOURNAL_FILE: &str = "telechecker.journal";
+
+/// Amount of accounts requested per `users.GetUsers`
+const GET_USERS_CHUNK_SIZE: usize = 100;
+
+/// Delay between two `auth.ExportLoginToken` while waiting for a QR code to be scanned
+const QR_LOGIN_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Amount of times a phone number, login code or password is asked for before giving up
+const SIGN_IN_ATTEMPTS: u32 = 3;
+
+/// This module provides the [`auth::AuthPrompter`] trait used to retrieve what is needed to sign
+/// in
+pub mod auth;
+
+/// This module provides the [`builder::UserRetrieverBuilder`] configuring how a [`UserRetriever`]
+/// connects to telegram
+pub mod builder;
+
+/// This module provides the batching layer used to import phone numbers without getting rate
+/// limited
+pub mod batch;
+
+/// This module provides the [`Error`] type returned by this crate
+pub mod error;
+
+pub use error::{Error, Result};
+
+/// This module provides the types returned when checking phone numbers, mapping each result back
+/// to the input that produced it
+pub mod check;
+
+/// This module provides the types used to remove the accounts added to the requesting user's
+/// contacts after a check, even if the process is interrupted
+pub mod cleanup;
+
+/// This module provides the types used to download the profile photos of the accounts found into
+/// an evidence directory
+pub mod photos;
+
+/// This module provides the [`session::SessionStore`] trait and the backends the session can be
+/// kept in
+pub mod session;
+
+/// This module provides the [`transport::Transport`] trait abstracting the telegram API, allowing
+/// the library to run against a mock
+pub mod transport;
+
 /// This module provides local types that are equivalent to types found in [`grammers_tl_types`] to
 /// allow them to be serializable
 pub mod user;
@@ -41,28 +105,139 @@ pub mod user;
 ///
 /// # Errors
 ///
-/// Returns [`Err`] if the [`phonenumber`] is unable to parse the provided number
+/// Returns [`Error::InvalidPhone`] if the [`phonenumber`] is unable to parse the provided number
 pub fn validate(user_phone: &str) -> Result<()> {
-    phonenumber::parse(None, user_phone).context("Validate phone number")?;
+    normalize(user_phone, None)?;
     debug!("Phone number is valid");
     Ok(())
 }
 
-/// Function that returns an [`InputContact`] given a `client_id` and `phone` number
+/// Function that formats a phone number as E.164 (i.e. `+14041234567`) using the [`phonenumber`]
+/// crate
+///
+/// Phone numbers without a country code (i.e. `(404) 123-4567`) or using a national international
+/// dialing prefix (i.e. `0044...`) are parsed as if they were dialed from `region`
 ///
 /// # Errors
 ///
-/// Returns [`Err`] if the phone number is incorrectly formatted or if the `client_id`
-/// is larger than an [`i64`]
-fn get_input_content((client_id, phone): (usize, String)) -> Result<InputContact> {
-    phonenumber::parse(None, &phone).context("Validate phone number")?;
-    let client_id = i64::try_from(client_id).context("Convert usize to i64")?;
-    Ok(InputContact::InputPhoneContact(InputPhoneContact {
+/// Returns [`Error::InvalidPhone`] if the [`phonenumber`] is unable to parse the provided number
+pub fn normalize(phone: &str, region: Option<country::Id>) -> Result<String> {
+    to_e164(phone, region).map_err(|source| Error::InvalidPhone {
+        input: phone.to_string(),
+        source,
+    })
+}
+
+/// Implementation of [`normalize`], returning the [`phonenumber::ParseError`] as is
+fn to_e164(phone: &str, region: Option<country::Id>) -> Result<String, phonenumber::ParseError> {
+    phonenumber::parse(region, phone).map(|number| number.format().mode(Mode::E164).to_string())
+}
+
+/// Function that returns whether `error` means the phone number provided to sign in should be
+/// asked for again: it is incorrectly formatted, telegram rejected it or it has no account
+fn is_invalid_phone(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::InvalidPhone { .. } | Error::SignIn(SignInFailure::SignUpRequired)
+    ) || matches!(error, Error::Rpc { name, .. } if name.as_str() == "PHONE_NUMBER_INVALID")
+}
+
+/// Function that extracts the username from `input`, accepting `@handle`, `handle` and
+/// `t.me/handle` links
+///
+/// # Errors
+///
+/// Returns [`Err`] with the reason if `input` is not 4 to 32 letters, digits or underscores
+/// starting with a letter
+fn parse_username(input: &str) -> Result<String, &'static str> {
+    let input = input.trim();
+    let username = ["https://t.me/", "http://t.me/", "t.me/", "@"]
+        .iter()
+        .find_map(|prefix| input.strip_prefix(prefix))
+        .unwrap_or(input);
+    if !(4..=32).contains(&username.len()) {
+        return Err("usernames are 4 to 32 characters long");
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err("usernames start with a letter");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err("usernames only contain letters, digits and underscores");
+    }
+    Ok(username.to_string())
+}
+
+/// A phone number that has been assigned a `client_id`, ready to be imported if it is valid
+struct PreparedContact {
+    client_id: i64,
+    input: String,
+    /// The other provided phone numbers that normalize to the same value
+    aliases: Vec<String>,
+    normalized: Result<String, phonenumber::ParseError>,
+}
+
+impl PreparedContact {
+    /// Returns the [`InputContact`] that should be sent to telegram for this phone number, or
+    /// [`None`] if the phone number is invalid
+    fn to_input_contact(&self) -> Option<InputContact> {
+        let phone = self.normalized.as_ref().ok()?;
+        Some(InputContact::InputPhoneContact(InputPhoneContact {
+            client_id: self.client_id,
+            phone: phone.clone(),
+            first_name: String::new(),
+            last_name: String::new(),
+        }))
+    }
+}
+
+/// Function that returns a [`PreparedContact`] given a `client_id` and `phone` number, normalized
+/// according to `region` (see [`normalize`])
+///
+/// An incorrectly formatted phone number does not fail this function; the parse error is kept in
+/// [`PreparedContact::normalized`] so it can be reported alongside the other results
+///
+/// # Errors
+///
+/// Returns [`Err`] if the `client_id` is larger than an [`i64`]
+fn get_input_content(
+    (client_id, phone): (usize, String),
+    region: Option<country::Id>,
+) -> Result<PreparedContact> {
+    let client_id = i64::try_from(client_id)
+        .map_err(|_| Error::Validation(format!("client_id {client_id} does not fit in an i64")))?;
+    let normalized = to_e164(&phone, region);
+    Ok(PreparedContact {
         client_id,
-        phone,
-        first_name: String::new(),
-        last_name: String::new(),
-    }))
+        input: phone,
+        aliases: Vec::new(),
+        normalized,
+    })
+}
+
+/// Function that merges the valid phone numbers normalizing to the same value into the first of
+/// them, recording the others as its [`PreparedContact::aliases`]
+fn dedup(prepared: Vec<PreparedContact>) -> Vec<PreparedContact> {
+    let mut deduped: Vec<PreparedContact> = Vec::with_capacity(prepared.len());
+    // normalized -> index in `deduped`
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for contact in prepared {
+        if let Ok(normalized) = &contact.normalized {
+            if let Some(&i) = seen.get(normalized) {
+                debug!(
+                    "'{}' is a duplicate of '{}'",
+                    contact.input, deduped[i].input
+                );
+                deduped[i].aliases.push(contact.input);
+                continue;
+            }
+            seen.insert(normalized.clone(), deduped.len());
+        }
+        deduped.push(contact);
+    }
+    deduped
 }
 
 /// Function that maps an [Iterator] of [User] (foreign type) into an [Iterator] of [`UserData`] (local)
@@ -73,155 +248,910 @@ fn into_user_data(users: impl Iterator<Item = User>) -> impl Iterator<Item = Use
     })
 }
 
+/// Converts the stripped thumbnail of the profile photo of `user` to a data URI
+fn add_thumbnail(user: &mut UserData) {
+    if let Some(photo) = &mut user.photo {
+        photo.thumbnail = photo.thumbnail_data_uri();
+    }
+}
+
+/// Splits `prepared` into batches holding at most `chunk_size` valid phone numbers each, keeping
+/// the invalid ones in place so the results can be yielded in the order they were provided
+fn into_batches(
+    prepared: Vec<PreparedContact>,
+    chunk_size: usize,
+) -> VecDeque<Vec<PreparedContact>> {
+    let mut batches = VecDeque::new();
+    let mut batch = Vec::new();
+    let mut valid = 0;
+    for contact in prepared {
+        if contact.normalized.is_ok() {
+            if valid == chunk_size.max(1) {
+                batches.push_back(std::mem::take(&mut batch));
+                valid = 0;
+            }
+            valid += 1;
+        }
+        batch.push(contact);
+    }
+    if !batch.is_empty() {
+        batches.push_back(batch);
+    }
+    batches
+}
+
+/// What telegram returned for a batch, whichever [`LookupStrategy`] was used
+#[derive(Default)]
+struct BatchResponse {
+    /// `client_id` -> `user_id`
+    user_ids: HashMap<i64, i64>,
+    /// `user_id` -> user
+    users: HashMap<i64, UserData>,
+    /// The `client_id`s of the phone numbers telegram refused to look up for now
+    retry_contacts: Vec<i64>,
+}
+
+/// The state of a check streamed by [`UserRetriever::get_users_stream`]
+struct Check<'s, 'a, T: Transport, C: Clock> {
+    retriever: &'s UserRetriever<'a, T, C>,
+    journal: Option<&'s mut Journal>,
+    /// The phone numbers to check, until they are split into `batches` on the first poll
+    numbers: Option<Vec<String>>,
+    batches: VecDeque<Vec<PreparedContact>>,
+    /// Snapshot of the requesting user's contacts, taken so cleanup never removes one of them.
+    /// Only taken with [`LookupStrategy::ImportContacts`]
+    existing_contacts: HashSet<i64>,
+    /// Results of the last batch that have not been yielded yet
+    ready: VecDeque<CheckResult>,
+    /// Whether a lookup request has already been sent
+    requested: bool,
+    /// The evidence directory of this check, created when the first photo is downloaded
+    run_dir: Option<PathBuf>,
+}
+
+impl<T: Transport, C: Clock + Sync> Check<'_, '_, T, C> {
+    /// Returns the next [`CheckResult`], importing the next batch if needed
+    async fn next(&mut self) -> Result<Option<CheckResult>> {
+        loop {
+            if let Some(result) = self.ready.pop_front() {
+                return Ok(Some(result));
+            }
+            if let Some(numbers) = self.numbers.take() {
+                let region = self.retriever.default_region;
+                let prepared: Vec<PreparedContact> = numbers
+                    .into_iter()
+                    .enumerate()
+                    .map(|number| get_input_content(number, region))
+                    .collect::<Result<_>>()?;
+                let prepared = dedup(prepared);
+                // `contacts.ResolvePhone` takes a single phone number, every request is a batch.
+                // It adds no contact, so there is nothing to protect from cleanup
+                let chunk_size = match self.retriever.lookup_strategy {
+                    LookupStrategy::ImportContacts => {
+                        self.existing_contacts = self.retriever.contact_ids().await?;
+                        self.retriever.batch_options.chunk_size
+                    }
+                    LookupStrategy::ResolvePhone => 1,
+                };
+                self.batches = into_batches(prepared, chunk_size);
+                continue;
+            }
+            let Some(batch) = self.batches.pop_front() else {
+                return Ok(None);
+            };
+            let results = self.check_batch(batch).await?;
+            self.ready.extend(results);
+        }
+    }
+
+    /// Imports the valid phone numbers of `batch` and returns a [`CheckResult`] for each of them
+    async fn check_batch(&mut self, batch: Vec<PreparedContact>) -> Result<Vec<CheckResult>> {
+        let contacts: Vec<InputContact> = batch
+            .iter()
+            .filter_map(PreparedContact::to_input_contact)
+            .collect();
+
+        let strategy = self.retriever.lookup_strategy;
+        let BatchResponse {
+            user_ids,
+            mut users,
+            retry_contacts,
+        } = if contacts.is_empty() {
+            BatchResponse::default()
+        } else {
+            let options = &self.retriever.batch_options;
+            if self.requested {
+                debug!("Waiting {:?} before the next batch", options.delay);
+                self.retriever.clock.sleep(options.delay).await;
+            }
+            self.requested = true;
+            match strategy {
+                LookupStrategy::ImportContacts => self.import(&batch, contacts).await?,
+                LookupStrategy::ResolvePhone => self.resolve(contacts).await?,
+            }
+        };
+        let retrieved_at = Timestamp::now();
+
+        let mut results: Vec<CheckResult> = batch
+            .into_iter()
+            .map(|contact| {
+                let (normalized, outcome) = match contact.normalized {
+                    Err(e) => (
+                        None,
+                        CheckOutcome::InvalidNumber {
+                            error: e.to_string(),
+                        },
+                    ),
+                    Ok(normalized) => {
+                        let outcome = if retry_contacts.contains(&contact.client_id) {
+                            CheckOutcome::RetryLater
+                        } else {
+                            user_ids
+                                .get(&contact.client_id)
+                                .and_then(|user_id| users.remove(user_id))
+                                .map_or(CheckOutcome::NotRegisteredOrHidden, |user| {
+                                    CheckOutcome::Found(Box::new(user))
+                                })
+                        };
+                        (Some(normalized), outcome)
+                    }
+                };
+                let existing_contact = matches!(
+                    &outcome,
+                    CheckOutcome::Found(user) if self.existing_contacts.contains(&user.id)
+                );
+                let last_seen = match &outcome {
+                    CheckOutcome::Found(user) => {
+                        user.status.as_ref().map(|s| s.last_seen(retrieved_at))
+                    }
+                    _ => None,
+                };
+                CheckResult {
+                    strategy,
+                    retrieved_at: normalized.as_ref().map(|_| retrieved_at),
+                    input: contact.input,
+                    aliases: contact.aliases,
+                    normalized,
+                    client_id: contact.client_id,
+                    outcome,
+                    existing_contact,
+                    last_seen,
+                    full_user: None,
+                    photos: None,
+                }
+            })
+            .collect();
+
+        self.enrich(&mut results).await?;
+
+        if let (LookupStrategy::ImportContacts, Some(journal)) =
+            (strategy, self.journal.as_deref_mut())
+        {
+            journal.record_added(&results)?;
+        }
+
+        Ok(results)
+    }
+
+    /// Imports `contacts` into the requesting user's contacts, recording them to the journal
+    /// first so they can be removed later
+    async fn import(
+        &mut self,
+        batch: &[PreparedContact],
+        contacts: Vec<InputContact>,
+    ) -> Result<BatchResponse> {
+        if let Some(journal) = self.journal.as_deref_mut() {
+            journal.record_pending(
+                batch
+                    .iter()
+                    .filter_map(|c| c.normalized.as_ref().ok().cloned()),
+                &self.existing_contacts,
+            )?;
+        }
+        debug!("Importing batch ({} contacts)", contacts.len());
+        let tl::types::contacts::ImportedContacts {
+            imported,
+            retry_contacts,
+            users,
+            ..
+        } = import_batch(
+            &self.retriever.transport,
+            &self.retriever.clock,
+            &self.retriever.batch_options,
+            contacts,
+        )
+        .await?;
+        Ok(BatchResponse {
+            user_ids: imported
+                .into_iter()
+                .map(|ImportedContact::Contact(c)| (c.client_id, c.user_id))
+                .collect(),
+            users: into_user_data(users.into_iter())
+                .map(|u| (u.id, u))
+                .collect(),
+            retry_contacts,
+        })
+    }
+
+    /// Resolves `contacts` one at a time with `contacts.ResolvePhone`, leaving the requesting
+    /// user's contacts untouched
+    async fn resolve(&self, contacts: Vec<InputContact>) -> Result<BatchResponse> {
+        let mut response = BatchResponse::default();
+        for InputContact::InputPhoneContact(contact) in contacts {
+            debug!("Resolving phone number {}", contact.client_id);
+            let resolved =
+                retry_flood_wait(&self.retriever.clock, &self.retriever.batch_options, || {
+                    self.retriever.transport.resolve_phone(&contact.phone)
+                })
+                .await;
+            let resolved = match resolved {
+                Ok(resolved) => resolved,
+                Err(Error::Rpc { name, .. }) if name == "PHONE_NOT_OCCUPIED" => continue,
+                Err(e) => return Err(e),
+            };
+            let tl::enums::Peer::User(peer) = resolved.peer else {
+                continue;
+            };
+            if let Some(user) =
+                into_user_data(resolved.users.into_iter()).find(|u| u.id == peer.user_id)
+            {
+                response.user_ids.insert(contact.client_id, user.id);
+                response.users.insert(user.id, user);
+            }
+        }
+        Ok(response)
+    }
+
+    /// Adds the details of the accounts found in `results` that were opted into (see
+    /// [`UserRetriever::with_thumbnails`], [`UserRetriever::with_full_users`] and
+    /// [`UserRetriever::with_photos`])
+    async fn enrich(&mut self, results: &mut [CheckResult]) -> Result<()> {
+        if self.retriever.thumbnails {
+            for result in results.iter_mut() {
+                if let CheckOutcome::Found(user) = &mut result.outcome {
+                    add_thumbnail(user);
+                }
+            }
+        }
+
+        if self.retriever.full_users {
+            for result in results.iter_mut() {
+                let Some(user) = result.user() else {
+                    continue;
+                };
+                match self.retriever.get_full_user(user).await {
+                    Ok(full_user) => result.full_user = Some(full_user),
+                    // the account may have become inaccessible since it was imported
+                    Err(e @ Error::Rpc { .. }) => {
+                        warn!("Unable to retrieve the full profile of {}: {e}", user.id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        if let Some(options) = &self.retriever.photo_options {
+            for result in results.iter_mut() {
+                let Some(user) = result.user() else {
+                    continue;
+                };
+                let run_dir = match &self.run_dir {
+                    Some(run_dir) => run_dir,
+                    None => self.run_dir.insert(create_run_dir(&options.evidence_dir)?),
+                };
+                match download_photos(&self.retriever.transport, user, options.history, run_dir)
+                    .await
+                {
+                    Ok(photos) => result.photos = Some(photos),
+                    Err(e @ Error::Rpc { .. }) => {
+                        warn!("Unable to download the photos of {}: {e}", user.id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// This is the fundemental type this crate provides.
 ///
-/// Provides an abstraction over a [`Client`] with methods to retrieve [`UserData`] from the
-/// telegram API
+/// Provides an abstraction over a [`Transport`] (by default a [`Client`]) with methods to retrieve
+/// [`UserData`] from the telegram API, waiting between requests with a [`Clock`] (by default a
+/// [`TokioClock`])
 ///
-pub struct UserRetriever<'a> {
-    client: Client,
-    sign_out: bool,
-    session_file: &'a str,
+#[allow(clippy::struct_excessive_bools)]
+pub struct UserRetriever<'a, T: Transport = Client, C: Clock = TokioClock> {
+    transport: T,
+    clock: C,
+    /// Whether the last attempt to save the session failed, in which case
+    /// [`UserRetriever::sign_out`] logs out since the session cannot be resumed
+    save_failed: bool,
+    ephemeral: bool,
+    session_store: Box<dyn SessionStore + 'a>,
+    /// The API credentials sent when signing in, and what to reconnect with when the account
+    /// belongs to another data center
+    connect_params: ConnectParams,
+    batch_options: BatchOptions,
+    lookup_strategy: LookupStrategy,
+    default_region: Option<country::Id>,
+    thumbnails: bool,
+    full_users: bool,
+    photo_options: Option<PhotoOptions>,
 }
 
-impl<'a> UserRetriever<'a> {
-    /// Returns a new [`UserRetriever`]
+impl<'a> UserRetriever<'a, Client> {
+    /// Returns a new [`UserRetriever`] keeping the session in `session_file` (see
+    /// [`UserRetriever::connect`])
     ///
     /// # Errors
     ///
     /// Returns an [`Err`] if the underlying [`grammers_client::Client`] fails to build
     ///
     pub async fn new(api_id: i32, api_hash: String, session_file: &'a str) -> Result<Self> {
-        let client = Client::connect(Config {
-            session: Session::load_file_or_create(session_file).context("Load/Create session")?,
-            api_id,
-            api_hash,
-            params: grammers_client::InitParams::default(),
-        })
-        .await
-        .context("build Client")?;
-        Ok(Self {
-            client,
-            sign_out: true,
-            session_file,
-        })
+        Self::connect(api_id, api_hash, FileSessionStore::new(session_file)).await
+    }
+
+    /// Returns a new [`UserRetriever`], resuming the session saved in `session_store` if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Err`] if the session cannot be loaded or the underlying
+    /// [`grammers_client::Client`] fails to build
+    pub async fn connect(
+        api_id: i32,
+        api_hash: String,
+        session_store: impl SessionStore + 'a,
+    ) -> Result<Self> {
+        Self::builder(api_id, api_hash)
+            .session_store(session_store)
+            .connect()
+            .await
+    }
+
+    /// Returns a [`UserRetrieverBuilder`] to configure how the [`UserRetriever`] connects to
+    /// telegram (i.e. through a proxy)
+    #[must_use]
+    pub fn builder(api_id: i32, api_hash: String) -> UserRetrieverBuilder<'a> {
+        UserRetrieverBuilder::new(api_id, api_hash)
+    }
+}
+
+impl<'a, T: Transport> UserRetriever<'a, T> {
+    /// Returns a new [`UserRetriever`] using the provided [`Transport`], saving its session to
+    /// `session_store`
+    pub fn with_transport(transport: T, session_store: impl SessionStore + 'a) -> Self {
+        Self {
+            transport,
+            clock: TokioClock,
+            save_failed: false,
+            ephemeral: false,
+            session_store: Box::new(session_store),
+            connect_params: ConnectParams::default(),
+            batch_options: BatchOptions::default(),
+            lookup_strategy: LookupStrategy::default(),
+            default_region: None,
+            thumbnails: false,
+            full_users: false,
+            photo_options: None,
+        }
+    }
+}
+
+impl<'a, T: Transport, C: Clock + Sync> UserRetriever<'a, T, C> {
+    /// Returns the [`UserRetriever`] waiting between requests (see [`BatchOptions`]) and through
+    /// `FLOOD_WAIT` errors with `clock` rather than actually sleeping
+    pub fn with_clock<K: Clock + Sync>(self, clock: K) -> UserRetriever<'a, T, K> {
+        UserRetriever {
+            transport: self.transport,
+            clock,
+            save_failed: self.save_failed,
+            ephemeral: self.ephemeral,
+            session_store: self.session_store,
+            connect_params: self.connect_params,
+            batch_options: self.batch_options,
+            lookup_strategy: self.lookup_strategy,
+            default_region: self.default_region,
+            thumbnails: self.thumbnails,
+            full_users: self.full_users,
+            photo_options: self.photo_options,
+        }
+    }
+
+    /// Sets whether the session is ephemeral: it is never saved to the [`SessionStore`], and
+    /// [`UserRetriever::sign_out`] logs it out and removes it from the [`SessionStore`] once
+    /// done, so a run leaves no authorization behind. Disabled by default
+    #[must_use]
+    pub const fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    /// Sets the region phone numbers without a country code are assumed to be from, both when
+    /// signing in and when checking phone numbers (see [`normalize`])
+    #[must_use]
+    pub const fn with_default_region(mut self, region: country::Id) -> Self {
+        self.default_region = Some(region);
+        self
+    }
+
+    /// Sets whether the stripped thumbnail of the profile photo of every account found is
+    /// converted to a JPEG data URI (see [`user::UserProfilePhoto::thumbnail`]). This sends no
+    /// additional request, and is disabled by default
+    #[must_use]
+    pub const fn with_thumbnails(mut self, thumbnails: bool) -> Self {
+        self.thumbnails = thumbnails;
+        self
+    }
+
+    /// Sets whether the full profile of every account found is requested (see
+    /// [`UserRetriever::get_full_user`] and [`CheckResult::full_user`]). This sends one more
+    /// request per account found, and is disabled by default
+    #[must_use]
+    pub const fn with_full_users(mut self, full_users: bool) -> Self {
+        self.full_users = full_users;
+        self
+    }
+
+    /// Sets whether and which profile photos of every account found are downloaded (see
+    /// [`PhotoOptions`] and [`CheckResult::photos`]). This sends a few more requests per account
+    /// found, and is disabled by default
+    #[must_use]
+    pub fn with_photos(mut self, photo_options: Option<PhotoOptions>) -> Self {
+        self.photo_options = photo_options;
+        self
+    }
+
+    /// Sets how accounts are looked up from phone numbers (see [`UserRetriever::get_users`]). By
+    /// default, they are imported as contacts
+    #[must_use]
+    pub const fn with_lookup_strategy(mut self, lookup_strategy: LookupStrategy) -> Self {
+        self.lookup_strategy = lookup_strategy;
+        self
+    }
+
+    /// Sets the [`BatchOptions`] used when importing phone numbers (see [`UserRetriever::get_users`])
+    #[must_use]
+    pub const fn with_batch_options(mut self, batch_options: BatchOptions) -> Self {
+        self.batch_options = batch_options;
+        self
+    }
+
+    /// Returns whether the requesting user is signed in, either because the session was resumed
+    /// or after [`UserRetriever::sign_in`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the request to telegram fails
+    pub async fn is_authorized(&self) -> Result<bool> {
+        self.transport.is_authorized().await
     }
 
-    /// This method signs the requesting user in.
+    /// This method signs the requesting user in with their phone number and the login code
+    /// telegram sends them.
     ///
-    /// In order to use this method, three closures must be provided:
-    /// 1. `phone_input_handler`: A function to retrieve the requesting user's phone number from some
-    ///    form of input
-    /// 2. `code_input_handler`: A function to retrieve the requesting user's verification code from some
-    ///    form of input (this happens after providing the user's phone)
-    /// 3. `password_input_handler`: In the event that the user also has a password set up, this
-    ///    function must retrieve the password from some form of input
+    /// The phone number, login code and password (if the user has one set up) are retrieved from
+    /// the `prompter`. Invalid phone numbers (or ones without an account), codes and passwords are
+    /// reported to it with [`AuthPrompter::notify_error`] and asked for again, up to three times.
+    /// After an invalid code, the code can be sent again (see [`AuthPrompter::resend`]), and a
+    /// new code is requested if it expired
     ///
-    /// This method will also attempt to save the current session into the session file specified
-    /// by [`UserRetriever::session_file`]
+    /// This method will also attempt to save the current session to the [`SessionStore`] the
+    /// [`UserRetriever`] was built with
     ///
     /// # Errors
     ///
     /// Errors on
     /// 1) any failure from [`grammers_client::Client`]
-    /// 2) if the handler functions return an [`Err`]
-    pub async fn sign_in<PH, CH, PWH, R1, R2, R3>(
-        &mut self,
-        phone_input_handler: PH,
-        code_input_handler: CH,
-        password_input_handler: PWH,
-    ) -> Result<()>
-    where
-        // Thre three handler functions
-        PH: FnOnce() -> R1 + Send + 'static,
-        CH: FnOnce() -> R2 + Send + 'static,
-        PWH: FnOnce(String) -> R3 + Send + 'static,
-        // Outputs of the three handler functions
-        R1: Future<Output = Result<String>> + Send + 'static,
-        R2: Future<Output = Result<String>> + Send + 'static,
-        R3: Future<Output = Result<String>> + Send + 'static,
-    {
-        if self.client.is_authorized().await? {
+    /// 2) if the `prompter` returns an [`Err`]
+    /// 3) if an invalid input is still provided after the last attempt
+    pub async fn sign_in(&mut self, prompter: &mut impl AuthPrompter) -> Result<()> {
+        if self.transport.is_authorized().await? {
             debug!("User is already authorized");
-        } else {
+            return Ok(());
+        }
+        let mut attempt = 1;
+        let step = loop {
             debug!("Awaiting input: user phone number");
-            let phone = phone_input_handler().await?;
-            validate(&phone)?;
-            let token = self.client.request_login_code(&phone).await?;
-            debug!("Awaiting input: user code");
-            let code = code_input_handler().await?;
-            let signed_in = self.client.sign_in(&token, &code).await;
-            match signed_in {
-                Err(SignInError::PasswordRequired(password_token)) => {
-                    let hint = password_token.hint().unwrap_or("None").to_string();
-                    debug!("Awaiting input: user password");
-                    let password = password_input_handler(hint).await?;
-
-                    self.client
-                        .check_password(password_token, password.trim())
+            let phone = prompter.phone().await.map_err(Error::Input)?;
+            let step = match normalize(&phone, self.default_region) {
+                Ok(phone) => self.enter_code(prompter, &phone).await,
+                Err(e) => Err(e),
+            };
+            match step {
+                Err(e) if attempt < SIGN_IN_ATTEMPTS && is_invalid_phone(&e) => {
+                    prompter.notify_error(&e).await;
+                    attempt += 1;
+                }
+                step => break step?,
+            }
+        };
+        self.enter_password(prompter, step).await?;
+        debug!("Signed in!");
+        self.try_save_session();
+        debug!("Session saved");
+        Ok(())
+    }
+
+    /// Requests a login code for `phone` and signs in with the code retrieved from the
+    /// `prompter`, returning whether a password is also required
+    async fn enter_code(
+        &mut self,
+        prompter: &mut impl AuthPrompter,
+        phone: &str,
+    ) -> Result<SignInStep<T::PasswordToken>> {
+        let (mut token, mut sent_code) = self
+            .transport
+            .request_login_code(phone, &self.connect_params)
+            .await?;
+        let mut attempt = 1;
+        loop {
+            debug!(
+                "Awaiting input: user code (sent through {})",
+                sent_code.delivery
+            );
+            let code = prompter
+                .code(&sent_code, attempt)
+                .await
+                .map_err(Error::Input)?;
+            match self.transport.sign_in(&token, code.trim()).await {
+                Err(e @ Error::SignIn(SignInFailure::InvalidCode))
+                    if attempt < SIGN_IN_ATTEMPTS =>
+                {
+                    prompter.notify_error(&e).await;
+                    attempt += 1;
+                    if sent_code.next.is_some()
+                        && prompter.resend(&sent_code).await.map_err(Error::Input)?
+                    {
+                        debug!("Sending the code again");
+                        (token, sent_code) = self.transport.resend_login_code(&token).await?;
+                    }
+                }
+                Err(e @ Error::SignIn(SignInFailure::CodeExpired))
+                    if attempt < SIGN_IN_ATTEMPTS =>
+                {
+                    prompter.notify_error(&e).await;
+                    attempt += 1;
+                    debug!("Requesting a new code");
+                    (token, sent_code) = self
+                        .transport
+                        .request_login_code(phone, &self.connect_params)
                         .await?;
                 }
-                Err(e) => anyhow::bail!("Unable to sign in: {e:?}"),
-                Ok(_) => {}
-            };
-            debug!("Signed in!");
-            self.try_save_session();
-            debug!("Session saved");
+                step => return step,
+            }
+        }
+    }
+
+    /// Finishes signing in with the password retrieved from the `prompter`, if `step` requires
+    /// one
+    async fn enter_password(
+        &self,
+        prompter: &mut impl AuthPrompter,
+        step: SignInStep<T::PasswordToken>,
+    ) -> Result<()> {
+        let SignInStep::PasswordRequired { mut token, hint } = step else {
+            return Ok(());
+        };
+        let mut attempt = 1;
+        loop {
+            debug!("Awaiting input: user password");
+            let password = prompter
+                .password(hint.as_deref(), attempt)
+                .await
+                .map_err(Error::Input)?;
+            match self.transport.check_password(token, password.trim()).await {
+                Err(e @ Error::SignIn(SignInFailure::InvalidPassword))
+                    if attempt < SIGN_IN_ATTEMPTS =>
+                {
+                    prompter.notify_error(&e).await;
+                    attempt += 1;
+                    token = self.transport.get_password().await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// This method signs the requesting user in by scanning a QR code from a device where they
+    /// are already signed in (in telegram's settings, under "Devices" > "Link Desktop Device"),
+    /// rather than with their phone number.
+    ///
+    /// The QR code is displayed with [`AuthPrompter::qr_login`], which is called again with a new
+    /// [`QrLogin`] every time the previous one expires. The password (if the user has one set up)
+    /// is retrieved from the `prompter` as in [`UserRetriever::sign_in`]
+    ///
+    /// Accounts of another data center are signed in with `auth.ImportLoginToken`, reconnecting
+    /// to their data center. This method will also attempt to save the current session to the
+    /// [`SessionStore`] the [`UserRetriever`] was built with
+    ///
+    /// # Errors
+    ///
+    /// Errors on
+    /// 1) any failure from [`grammers_client::Client`]
+    /// 2) if the `prompter` returns an [`Err`]
+    pub async fn sign_in_qr(&mut self, prompter: &mut impl AuthPrompter) -> Result<()> {
+        if self.transport.is_authorized().await? {
+            debug!("User is already authorized");
+            return Ok(());
         }
+        let mut displayed = None;
+        let mut step = self
+            .transport
+            .export_login_token(&self.connect_params)
+            .await?;
+        let step = loop {
+            step = match step {
+                QrLoginStep::Token { token, expires } => {
+                    if displayed.as_ref() != Some(&token) {
+                        debug!("Awaiting input: QR code scan");
+                        prompter
+                            .qr_login(&QrLogin::new(&token, expires))
+                            .await
+                            .map_err(Error::Input)?;
+                        displayed = Some(token);
+                    }
+                    self.clock.sleep(QR_LOGIN_POLL_INTERVAL).await;
+                    self.transport
+                        .export_login_token(&self.connect_params)
+                        .await?
+                }
+                QrLoginStep::MigrateTo { dc_id, token } => {
+                    debug!("Importing the login token to DC {dc_id}");
+                    self.transport
+                        .import_login_token(dc_id, token, &self.connect_params)
+                        .await?
+                }
+                QrLoginStep::Accepted(step) => break step,
+            };
+        };
+        self.enter_password(prompter, step).await?;
+        debug!("Signed in!");
+        self.try_save_session();
+        debug!("Session saved");
         Ok(())
     }
 
-    /// Attempts to save the session to [`UserRetriever::session_file`], failing silently if unable
+    /// Attempts to save the session to the [`SessionStore`], failing silently if unable. Nothing
+    /// is saved if the [`UserRetriever`] is ephemeral (see [`UserRetriever::with_ephemeral`])
     pub fn try_save_session(&mut self) {
-        match self.client.session().save_to_file(self.session_file) {
+        if self.ephemeral {
+            debug!("Ephemeral session, not saving it");
+            return;
+        }
+        match self.session_store.save(&self.transport.export_session()) {
             Ok(()) => {
-                self.sign_out = false;
+                self.save_failed = false;
             }
             Err(e) => {
-                warn!(
-                    "NOTE: failed to save the session to {}: {}",
-                    self.session_file, e
-                );
+                warn!("NOTE: failed to save the session: {e}");
+                self.save_failed = true;
             }
         }
     }
 
-    /// Requests to import the provided [`InputContact`]s into the requesting user's contacts
+    /// Logs the requesting user out with `auth.LogOut` and removes the session from the
+    /// [`SessionStore`], so the next run has to sign in again
     ///
-    /// # Note
+    /// # Errors
     ///
-    /// This method is the core of the [`UserRetriever`], our strategy of retrieving [`UserData`] requires we add the
-    /// prvoided phone numbers to the requesting user's contacts
-    async fn import_contacts(&self, contacts: Vec<InputContact>) -> Result<Vec<User>> {
-        let ImportedContacts::Contacts(tl::types::contacts::ImportedContacts { users, .. }) = self
-            .client
-            .invoke(&tl::functions::contacts::ImportContacts { contacts })
-            .await?;
-        Ok(users)
+    /// Returns [`Err`] if the request to telegram fails or the session cannot be removed
+    pub async fn log_out(&mut self) -> Result<()> {
+        self.transport.log_out().await?;
+        self.session_store.clear()?;
+        self.save_failed = false;
+        debug!("Logged out");
+        Ok(())
+    }
+
+    /// Ends the run, logging out (see [`UserRetriever::log_out`]) if the [`UserRetriever`] is
+    /// ephemeral or saving its session failed, so no authorization is left behind that could not
+    /// be resumed. A session resumed from the [`SessionStore`] is kept. Returns whether the
+    /// requesting user was logged out
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if logging out fails
+    pub async fn sign_out(&mut self) -> Result<bool> {
+        if !(self.ephemeral || self.save_failed) || !self.transport.is_authorized().await? {
+            return Ok(false);
+        }
+        self.log_out().await?;
+        Ok(true)
     }
 
     /// A validated [`UserRetriever`] (see [`UserRetriever::sign_in`]) can call this method
     /// on an [Iterator] of phone numbers.
     ///
+    /// The phone numbers are imported in batches according to the [`BatchOptions`] (see
+    /// [`UserRetriever::with_batch_options`]).
+    ///
+    /// A [`CheckResult`] is returned for every provided phone number, in the order they were
+    /// provided. Each one pairs the input with its [`CheckOutcome`], so numbers without an account,
+    /// numbers telegram asked us to retry later and incorrectly formatted numbers are all reported
+    /// rather than dropped.
+    ///
+    /// See [`UserRetriever::get_users_stream`] to receive the results as each batch completes
+    ///
     /// # Errors
     ///
-    /// Will return [Err] if the [`UserRetriever`] is not authorized or if the provided phone
-    /// numbers are not correctly formated
-    pub async fn get_users(&self, numbers: Vec<String>) -> Result<impl Iterator<Item = UserData>> {
-        let Ok(contacts): Result<Vec<InputContact>> = numbers
-            .into_iter()
-            .enumerate()
-            .map(get_input_content)
-            .collect()
-        else {
-            anyhow::bail!("Unable to create contacts request from provided phone numbers.");
+    /// Will return [Err] if the [`UserRetriever`] is not authorized or if the request to telegram
+    /// fails
+    pub async fn get_users(&self, numbers: Vec<String>) -> Result<Vec<CheckResult>> {
+        self.get_users_stream(numbers).try_collect().await
+    }
+
+    /// Equivalent to [`UserRetriever::get_users`], but yields the [`CheckResult`]s as soon as the
+    /// batch they belong to has been imported, in the order the phone numbers were provided
+    ///
+    /// Nothing is requested until the stream is polled. The stream ends after the first [`Err`]
+    /// it yields; the results yielded before it are unaffected
+    pub fn get_users_stream(
+        &self,
+        numbers: Vec<String>,
+    ) -> impl Stream<Item = Result<CheckResult>> + Send + '_ {
+        self.check_stream(numbers, None)
+    }
+
+    /// Implementation of [`UserRetriever::get_users_stream`], recording the imported phone numbers
+    /// and the accounts added to the requesting user's contacts into `journal` if one is provided
+    pub(crate) fn check_stream<'s>(
+        &'s self,
+        numbers: Vec<String>,
+        journal: Option<&'s mut Journal>,
+    ) -> impl Stream<Item = Result<CheckResult>> + Send + 's {
+        let check = Check {
+            retriever: self,
+            journal,
+            numbers: Some(numbers),
+            batches: VecDeque::new(),
+            existing_contacts: HashSet::new(),
+            ready: VecDeque::new(),
+            requested: false,
+            run_dir: None,
         };
+        stream::try_unfold(check, |mut check| async move {
+            Ok(check.next().await?.map(|result| (result, check)))
+        })
+    }
+
+    /// Resolves every username in `usernames` (i.e. `@handle` or `t.me/handle`) with
+    /// `contacts.ResolveUsername`, waiting [`BatchOptions::delay`] between requests
+    ///
+    /// Unlike [`UserRetriever::get_users`], nothing is added to the requesting user's contacts.
+    /// Only [`UserRetriever::with_thumbnails`] applies to the accounts found: the lookup strategy,
+    /// full profiles and photos are ignored
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if a request to telegram fails for any reason other than the username not
+    /// being used or being invalid
+    pub async fn resolve_usernames(&self, usernames: Vec<String>) -> Result<Vec<UsernameResult>> {
+        let mut results = Vec::with_capacity(usernames.len());
+        let mut requested = false;
+        for input in usernames {
+            let username = match parse_username(&input) {
+                Ok(username) => username,
+                Err(error) => {
+                    results.push(UsernameResult {
+                        input,
+                        username: None,
+                        outcome: UsernameOutcome::InvalidUsername {
+                            error: error.to_string(),
+                        },
+                        retrieved_at: None,
+                    });
+                    continue;
+                }
+            };
+            if requested {
+                debug!(
+                    "Waiting {:?} before the next username",
+                    self.batch_options.delay
+                );
+                self.clock.sleep(self.batch_options.delay).await;
+            }
+            requested = true;
+
+            debug!("Resolving username '{username}'");
+            let resolved = retry_flood_wait(&self.clock, &self.batch_options, || {
+                self.transport.resolve_username(&username)
+            })
+            .await;
+            let mut outcome = match resolved {
+                Ok(resolved) => UsernameOutcome::from(resolved),
+                Err(Error::Rpc { name, .. }) if name == "USERNAME_NOT_OCCUPIED" => {
+                    UsernameOutcome::NotFound
+                }
+                Err(Error::Rpc { name, .. }) if name == "USERNAME_INVALID" => {
+                    UsernameOutcome::InvalidUsername { error: name }
+                }
+                Err(e) => return Err(e),
+            };
+            if let (true, UsernameOutcome::Found(user) | UsernameOutcome::Bot(user)) =
+                (self.thumbnails, &mut outcome)
+            {
+                add_thumbnail(user);
+            }
+            results.push(UsernameResult {
+                input,
+                username: Some(username),
+                outcome,
+                retrieved_at: Some(Timestamp::now()),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Requests the current data (status, names, usernames, photo, ...) of previously found
+    /// `users` with `users.GetUsers`, using their stored `id` and `access_hash` rather than
+    /// importing their phone numbers again
+    ///
+    /// Accounts telegram no longer returns (i.e. deleted accounts, or an `access_hash` that is no
+    /// longer valid) are left out of the returned [`UserData`]s
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the request to telegram fails
+    pub async fn refresh_users(&self, users: &[UserData]) -> Result<Vec<UserData>> {
+        let mut refreshed = Vec::with_capacity(users.len());
+        for chunk in users.chunks(GET_USERS_CHUNK_SIZE) {
+            debug!("Refreshing {} accounts", chunk.len());
+            let input_users = chunk.iter().map(InputUser::from).collect();
+            let response = self.transport.get_users(input_users).await?;
+            refreshed.extend(into_user_data(response.into_iter()).map(|mut user| {
+                if self.thumbnails {
+                    add_thumbnail(&mut user);
+                }
+                user
+            }));
+        }
+        Ok(refreshed)
+    }
 
-        let users = self.import_contacts(contacts).await?;
-        Ok(into_user_data(users.into_iter()))
+    /// Requests the full profile of `user` with `users.GetFullUser`, holding the details
+    /// `contacts.ImportContacts` does not return (i.e. the bio or the common chats count)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the request to telegram fails
+    pub async fn get_full_user(&self, user: &UserData) -> Result<FullUserData> {
+        debug!("Requesting the full profile of {}", user.id);
+        let full_user = self.transport.get_full_user(user.into()).await?;
+        Ok(full_user.into())
+    }
+
+    /// Requests the requesting user's contacts
+    pub(crate) async fn contacts(&self) -> Result<tl::types::contacts::Contacts> {
+        self.transport.get_contacts().await
+    }
+
+    /// Requests the ids of every account currently in the requesting user's contacts
+    async fn contact_ids(&self) -> Result<HashSet<i64>> {
+        Ok(self
+            .contacts()
+            .await?
+            .contacts
+            .into_iter()
+            .map(|tl::enums::Contact::Contact(c)| c.user_id)
+            .collect())
+    }
+
+    /// Requests to delete the provided [`InputUser`]s from the users contacts
+    pub(crate) async fn delete_input_users(&self, users: Vec<InputUser>) -> Result<()> {
+        if users.is_empty() {
+            debug!("No contacts to remove");
+            return Ok(());
+        }
+        debug!("Removing contacts");
+        self.transport.delete_contacts(users).await
     }
 
-    /// Requests to delete the provided [`UserData`]s from the users contacts
+    /// Requests to delete the accounts found by [`UserRetriever::get_users`] from the users contacts
+    ///
+    /// Only the accounts that were added by the check itself are deleted: any account that was
+    /// already one of the requesting user's contacts before the check (see
+    /// [`CheckResult::existing_contact`]) is skipped and reported in the returned [`CleanupReport`]
     ///
     /// # Note
     ///
@@ -232,21 +1162,875 @@ impl<'a> UserRetriever<'a> {
     ///
     /// Returns an [`Err`] if the underlying call to
     /// [`grammers_tl_types::functions::contacts::DeleteContacts`] fails
-    pub async fn delete_contacts(&self, users: &[UserData]) -> Result<()> {
-        debug!("Removing contacts");
-        let delete_users: Vec<InputUser> = users.iter().map(InputUser::from).collect();
+    pub async fn delete_contacts(&self, results: &[CheckResult]) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+        let mut delete_users: Vec<InputUser> = Vec::new();
+        for result in results {
+            let Some(user) = result.user() else {
+                continue;
+            };
+            if result.existing_contact {
+                debug!("Skipping pre-existing contact {}", user.id);
+                report.skipped.push(user.id);
+            } else {
+                delete_users.push(user.into());
+                report.deleted.push(user.id);
+            }
+        }
 
-        let u = self
-            .client
-            .invoke(&tl::functions::contacts::DeleteContacts { id: delete_users })
-            .await?;
-        trace!("Updates: {u:?}");
-        Ok(())
+        self.delete_input_users(delete_users).await?;
+        Ok(report)
     }
 
-    /// Consumes self and returns the internal [`grammers_client::Client`]
+    /// Runs `f` in an [`ImportSession`] journaled to `journal_file`, then removes the accounts
+    /// it added to the requesting user's contacts, whatever `f` returned. Returns the output of
+    /// `f`, along with what was removed or [`None`] if the accounts were kept (see
+    /// [`ImportSession::keep_contacts`])
+    ///
+    /// If `journal_file` holds an unfinished cleanup, it is finished first (see
+    /// [`UserRetriever::recover_cleanup`])
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Err`] if the journal cannot be read, written or removed, or if removing the
+    /// accounts fails. The journal is kept in that case
+    pub async fn with_import_session<R>(
+        &self,
+        journal_file: impl Into<PathBuf>,
+        f: impl AsyncFnOnce(&mut ImportSession<'_, 'a, T, C>) -> R,
+    ) -> Result<(R, Option<CleanupReport>)> {
+        let journal_file = journal_file.into();
+        if let Some(report) = self.recover_cleanup(&journal_file).await? {
+            debug!(
+                "Removed {} contacts left behind by a previous session",
+                report.deleted.len()
+            );
+        }
+        let mut session = ImportSession::new(self, journal_file);
+        let output = f(&mut session).await;
+        let report = session.finish().await?;
+        Ok((output, report))
+    }
+
+    /// Finishes the cleanup left unfinished by a previous [`ImportSession`] journaled to
+    /// `journal_file`, for example because the process panicked or was killed
+    ///
+    /// This should be called on startup, once signed in (see [`UserRetriever::sign_in`]).
+    /// Returns [`None`] if there was nothing to clean up
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Err`] if the journal cannot be read or removed, or if the requests to
+    /// telegram fail. The journal is kept in that case so the cleanup can be attempted again
+    pub async fn recover_cleanup(
+        &self,
+        journal_file: impl AsRef<Path>,
+    ) -> Result<Option<CleanupReport>> {
+        let journal_file = journal_file.as_ref();
+        let Some(journal) = Journal::load(journal_file)? else {
+            return Ok(None);
+        };
+        warn!(
+            "Found an unfinished cleanup in '{}', finishing it",
+            journal_file.display()
+        );
+        let report = journal.cleanup(self).await?;
+        Journal::remove(journal_file)?;
+        Ok(Some(report))
+    }
+
+    /// Consumes self and returns the internal [`Transport`] (by default a
+    /// [`grammers_client::Client`])
     #[must_use]
-    pub fn into_inner(self) -> Client {
-        self.client
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::SentCode,
+        batch::FakeClock,
+        session::MemorySessionStore,
+        transport::{FakeCall, FakePasswordToken, FakeTransport},
+    };
+    use tl::types::contacts::ImportedContacts;
+
+    /// [`AuthPrompter`] answering with scripted inputs and recording what it was told
+    #[derive(Default)]
+    struct ScriptedPrompter {
+        phones: VecDeque<&'static str>,
+        codes: VecDeque<&'static str>,
+        passwords: VecDeque<&'static str>,
+        resend: bool,
+        hints: Vec<Option<String>>,
+        errors: Vec<String>,
+        qr_logins: Vec<QrLogin>,
+    }
+
+    impl AuthPrompter for ScriptedPrompter {
+        async fn phone(&mut self) -> anyhow::Result<String> {
+            self.phones
+                .pop_front()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("no phone left"))
+        }
+
+        async fn code(&mut self, _sent_code: &SentCode, _attempt: u32) -> anyhow::Result<String> {
+            self.codes
+                .pop_front()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("no code left"))
+        }
+
+        async fn resend(&mut self, _sent_code: &SentCode) -> anyhow::Result<bool> {
+            Ok(self.resend)
+        }
+
+        async fn password(&mut self, hint: Option<&str>, _attempt: u32) -> anyhow::Result<String> {
+            self.hints.push(hint.map(str::to_string));
+            self.passwords
+                .pop_front()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("no password left"))
+        }
+
+        async fn qr_login(&mut self, qr_login: &QrLogin) -> anyhow::Result<()> {
+            self.qr_logins.push(qr_login.clone());
+            Ok(())
+        }
+
+        async fn notify_error(&mut self, error: &Error) {
+            self.errors.push(error.to_string());
+        }
+    }
+
+    fn imported(contacts: &[(i64, i64)], users: Vec<tl::types::User>) -> ImportedContacts {
+        ImportedContacts {
+            imported: contacts
+                .iter()
+                .map(|&(client_id, user_id)| {
+                    tl::types::ImportedContact { user_id, client_id }.into()
+                })
+                .collect(),
+            popular_invites: Vec::new(),
+            retry_contacts: Vec::new(),
+            users: users.into_iter().map(User::User).collect(),
+        }
+    }
+
+    fn numbers(numbers: &[&str]) -> Vec<String> {
+        numbers.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn normalize_parses_national_numbers_with_a_default_region() {
+        assert_eq!(
+            normalize("(415) 555-2671", Some(country::Id::US)).unwrap(),
+            "+14155552671"
+        );
+        assert_eq!(
+            normalize("020 7946 0958", Some(country::Id::GB)).unwrap(),
+            "+442079460958"
+        );
+        assert_eq!(
+            normalize("0044 20 7946 0958", Some(country::Id::GB)).unwrap(),
+            "+442079460958"
+        );
+    }
+
+    #[test]
+    fn normalize_rejects_national_numbers_without_a_region() {
+        assert!(matches!(
+            normalize("(415) 555-2671", None),
+            Err(Error::InvalidPhone { input, .. }) if input == "(415) 555-2671"
+        ));
+    }
+
+    #[test]
+    fn normalize_ignores_the_region_of_international_numbers() {
+        for region in [None, Some(country::Id::US), Some(country::Id::GB)] {
+            assert_eq!(
+                normalize("+44 20 7946 0958", region).unwrap(),
+                "+442079460958"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn get_users_reports_every_outcome() {
+        let transport = FakeTransport::new().authorized(true);
+        let mut response = imported(
+            &[(0, 10), (1, 11)],
+            vec![FakeTransport::user(10, Some("14155552671".into()))],
+        );
+        response.retry_contacts.push(3);
+        transport.push_import(Ok(response));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let results = retriever
+            .get_users(numbers(&[
+                "+14155552671",
+                // imported, but the account hides itself from phone number lookups
+                "+14155552672",
+                "not a number",
+                "+14155552673",
+                "+1 (415) 555-2671",
+            ]))
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+        assert_eq!(results[0].normalized.as_deref(), Some("+14155552671"));
+        assert_eq!(results[0].aliases, ["+1 (415) 555-2671"]);
+        assert!(!results[0].existing_contact);
+        assert!(results[0].retrieved_at.is_some());
+        assert_eq!(results[1].outcome, CheckOutcome::NotRegisteredOrHidden);
+        assert!(matches!(
+            results[2].outcome,
+            CheckOutcome::InvalidNumber { .. }
+        ));
+        assert!(results[2].retrieved_at.is_none());
+        assert_eq!(results[3].outcome, CheckOutcome::RetryLater);
+
+        let imports: Vec<FakeCall> = retriever
+            .into_inner()
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, FakeCall::ImportContacts { .. }))
+            .collect();
+        assert_eq!(
+            imports,
+            [FakeCall::ImportContacts {
+                phones: numbers(&["+14155552671", "+14155552672", "+14155552673"])
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_users_parses_national_numbers_with_the_default_region() {
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_user(FakeTransport::user(10, Some("442079460958".into())));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_lookup_strategy(LookupStrategy::ResolvePhone)
+            .with_default_region(country::Id::GB);
+
+        let results = retriever
+            .get_users(numbers(&["020 7946 0958"]))
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].normalized.as_deref(), Some("+442079460958"));
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+    }
+
+    #[tokio::test]
+    async fn resolve_phone_leaves_the_contacts_alone() {
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_user(FakeTransport::user(10, Some("14155552671".into())));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_lookup_strategy(LookupStrategy::ResolvePhone)
+            .with_batch_options(BatchOptions {
+                delay: Duration::ZERO,
+                ..BatchOptions::default()
+            });
+
+        let results = retriever
+            .get_users(numbers(&["+14155552671", "+14155552672"]))
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+        assert_eq!(results[1].outcome, CheckOutcome::NotRegisteredOrHidden);
+        assert_eq!(
+            retriever.into_inner().calls(),
+            [
+                FakeCall::ResolvePhone {
+                    phone: "+14155552671".to_string()
+                },
+                FakeCall::ResolvePhone {
+                    phone: "+14155552672".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_users_waits_between_batches_and_through_flood_waits() {
+        let transport = FakeTransport::new().authorized(true);
+        transport.push_import(Ok(imported(
+            &[(0, 10)],
+            vec![FakeTransport::user(10, Some("14155552671".into()))],
+        )));
+        transport.push_import(Err(Error::RateLimited(Duration::from_secs(30))));
+        transport.push_import(Ok(imported(
+            &[(1, 11)],
+            vec![FakeTransport::user(11, Some("14155552672".into()))],
+        )));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_clock(FakeClock::new())
+            .with_batch_options(BatchOptions {
+                chunk_size: 1,
+                delay: Duration::from_secs(5),
+                max_flood_wait: Some(Duration::from_mins(1)),
+            });
+
+        let results = retriever
+            .get_users(numbers(&["+14155552671", "+14155552672"]))
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+        assert_eq!(results[1].user().map(|u| u.id), Some(11));
+        assert_eq!(
+            retriever.clock.sleeps(),
+            [Duration::from_secs(5), Duration::from_secs(30)]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_users_fails_above_max_flood_wait() {
+        let transport = FakeTransport::new().authorized(true);
+        transport.push_import(Err(Error::RateLimited(Duration::from_secs(61))));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_clock(FakeClock::new())
+            .with_batch_options(BatchOptions {
+                max_flood_wait: Some(Duration::from_mins(1)),
+                ..Default::default()
+            });
+
+        let results = retriever.get_users(numbers(&["+14155552671"])).await;
+
+        assert!(matches!(results, Err(Error::RateLimited(_))));
+        assert!(retriever.clock.sleeps().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_contacts_keeps_existing_contacts() {
+        let existing = FakeTransport::user(20, Some("14155552672".into()));
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_contact(existing.clone());
+        transport.push_import(Ok(imported(
+            &[(0, 10), (1, 20)],
+            vec![
+                FakeTransport::user(10, Some("14155552671".into())),
+                existing,
+            ],
+        )));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let results = retriever
+            .get_users(numbers(&["+14155552671", "+14155552672"]))
+            .await
+            .unwrap();
+        assert!(!results[0].existing_contact);
+        assert!(results[1].existing_contact);
+
+        let report = retriever.delete_contacts(&results).await.unwrap();
+        assert_eq!(report.deleted, [10]);
+        assert_eq!(report.skipped, [20]);
+        assert_eq!(retriever.into_inner().contact_ids(), [20]);
+    }
+
+    fn full_user(id: i64) -> tl::types::UserFull {
+        tl::types::UserFull {
+            blocked: false,
+            phone_calls_available: true,
+            phone_calls_private: false,
+            can_pin_message: true,
+            has_scheduled: false,
+            video_calls_available: true,
+            voice_messages_forbidden: false,
+            translations_disabled: false,
+            stories_pinned_available: false,
+            blocked_my_stories_from: false,
+            id,
+            about: Some("bio".into()),
+            settings: tl::types::PeerSettings {
+                report_spam: false,
+                add_contact: false,
+                block_contact: false,
+                share_contact: false,
+                need_contacts_exception: false,
+                report_geo: false,
+                autoarchived: false,
+                invite_members: false,
+                request_chat_broadcast: false,
+                geo_distance: None,
+                request_chat_title: None,
+                request_chat_date: None,
+            }
+            .into(),
+            personal_photo: None,
+            profile_photo: Some(tl::types::PhotoEmpty { id: 5 }.into()),
+            fallback_photo: None,
+            notify_settings: tl::types::PeerNotifySettings {
+                show_previews: None,
+                silent: None,
+                mute_until: None,
+                ios_sound: None,
+                android_sound: None,
+                other_sound: None,
+                stories_muted: None,
+                stories_hide_sender: None,
+                stories_ios_sound: None,
+                stories_android_sound: None,
+                stories_other_sound: None,
+            }
+            .into(),
+            bot_info: None,
+            pinned_msg_id: Some(42),
+            common_chats_count: 3,
+            folder_id: None,
+            ttl_period: Some(86_400),
+            theme_emoticon: None,
+            private_forward_name: Some("Alice".into()),
+            bot_group_admin_rights: None,
+            bot_broadcast_admin_rights: None,
+            premium_gifts: None,
+            wallpaper: None,
+            stories: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn get_users_adds_the_full_profile() {
+        let transport = FakeTransport::new()
+            .authorized(true)
+            .with_full_user(full_user(10));
+        transport.push_import(Ok(imported(
+            &[(0, 10)],
+            vec![FakeTransport::user(10, Some("14155552671".into()))],
+        )));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_full_users(true);
+
+        let results = retriever
+            .get_users(numbers(&["+14155552671"]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results[0].full_user,
+            Some(FullUserData {
+                phone_calls_available: true,
+                can_pin_message: true,
+                video_calls_available: true,
+                id: 10,
+                about: Some("bio".into()),
+                pinned_msg_id: Some(42),
+                common_chats_count: 3,
+                ttl_period: Some(86_400),
+                private_forward_name: Some("Alice".into()),
+                ..Default::default()
+            })
+        );
+        assert!(retriever
+            .into_inner()
+            .calls()
+            .contains(&FakeCall::GetFullUser { user_id: 10 }));
+    }
+
+    #[tokio::test]
+    async fn get_users_skips_an_inaccessible_full_profile() {
+        let transport = FakeTransport::new().authorized(true);
+        transport.push_import(Ok(imported(
+            &[(0, 10)],
+            vec![FakeTransport::user(10, Some("14155552671".into()))],
+        )));
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_full_users(true);
+
+        let results = retriever
+            .get_users(numbers(&["+14155552671"]))
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+        assert_eq!(results[0].full_user, None);
+        assert!(matches!(
+            retriever.get_full_user(results[0].user().unwrap()).await,
+            Err(Error::Rpc { name, .. }) if name == "USER_ID_INVALID"
+        ));
+    }
+
+    #[tokio::test]
+    async fn refresh_users_requests_chunks_and_leaves_out_missing_accounts() {
+        let mut transport = FakeTransport::new().authorized(true);
+        for id in (1..=205).filter(|id| ![50, 150].contains(id)) {
+            let mut user = FakeTransport::user(id, None);
+            user.deleted = id == 120;
+            transport = transport.with_user(user);
+        }
+        let users: Vec<UserData> = (1..=205)
+            .map(|id| FakeTransport::user(id, None).into())
+            .collect();
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new());
+
+        let refreshed = retriever.refresh_users(&users).await.unwrap();
+
+        let ids: Vec<i64> = refreshed.iter().map(|u| u.id).collect();
+        let expected: Vec<i64> = (1..=205).filter(|id| ![50, 150].contains(id)).collect();
+        assert_eq!(ids, expected);
+        assert!(refreshed.iter().find(|u| u.id == 120).unwrap().deleted);
+        let chunks: Vec<Vec<i64>> = retriever
+            .into_inner()
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                FakeCall::GetUsers { user_ids } => Some(user_ids),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                (1..=100).collect::<Vec<i64>>(),
+                (101..=200).collect(),
+                (201..=205).collect(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_username_accepts_handles_and_links() {
+        for input in [
+            "durov_bot",
+            "@durov_bot",
+            " t.me/durov_bot ",
+            "http://t.me/durov_bot",
+            "https://t.me/durov_bot",
+        ] {
+            assert_eq!(parse_username(input).as_deref(), Ok("durov_bot"), "{input}");
+        }
+    }
+
+    #[test]
+    fn parse_username_rejects_invalid_usernames() {
+        for (input, error) in [
+            ("@abc", "usernames are 4 to 32 characters long"),
+            (
+                "https://t.me/abcdefghijklmnopqrstuvwxyz0123456",
+                "usernames are 4 to 32 characters long",
+            ),
+            ("@1abcd", "usernames start with a letter"),
+            ("_abcd", "usernames start with a letter"),
+            (
+                "t.me/ab-cd",
+                "usernames only contain letters, digits and underscores",
+            ),
+            (
+                "https://t.me/joinchat/abcd",
+                "usernames only contain letters, digits and underscores",
+            ),
+        ] {
+            assert_eq!(parse_username(input), Err(error), "{input}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_usernames_reports_every_outcome() {
+        let mut user = FakeTransport::user(10, None);
+        user.username = Some("Alice_Smith".into());
+        let transport = FakeTransport::new().authorized(true).with_user(user);
+        let retriever = UserRetriever::with_transport(transport, MemorySessionStore::new())
+            .with_clock(FakeClock::new())
+            .with_batch_options(BatchOptions {
+                delay: Duration::from_secs(2),
+                ..Default::default()
+            });
+
+        let results = retriever
+            .resolve_usernames(numbers(&[
+                "https://t.me/alice_smith",
+                "@no",
+                "@nobody_here",
+            ]))
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].username.as_deref(), Some("alice_smith"));
+        assert_eq!(results[0].user().map(|u| u.id), Some(10));
+        assert!(results[0].retrieved_at.is_some());
+        assert_eq!(results[1].username, None);
+        assert!(matches!(
+            results[1].outcome,
+            UsernameOutcome::InvalidUsername { .. }
+        ));
+        assert_eq!(results[1].retrieved_at, None);
+        assert_eq!(results[2].username.as_deref(), Some("nobody_here"));
+        assert_eq!(results[2].outcome, UsernameOutcome::NotFound);
+        assert_eq!(retriever.clock.sleeps(), [Duration::from_secs(2)]);
+        assert_eq!(
+            retriever.into_inner().calls(),
+            [
+                FakeCall::ResolveUsername {
+                    username: "alice_smith".into()
+                },
+                FakeCall::ResolveUsername {
+                    username: "nobody_here".into()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_retries_invalid_phone_and_code() {
+        let transport = FakeTransport::new();
+        transport.push_sign_in(Err(Error::SignIn(SignInFailure::InvalidCode)));
+        let store = MemorySessionStore::new();
+        let mut retriever = UserRetriever::with_transport(transport, &store);
+        let mut prompter = ScriptedPrompter {
+            phones: ["not a number", "+14155552671"].into(),
+            codes: ["00000", " 12345 "].into(),
+            resend: true,
+            ..Default::default()
+        };
+
+        retriever.sign_in(&mut prompter).await.unwrap();
+
+        assert_eq!(prompter.errors.len(), 2);
+        assert!(prompter.hints.is_empty());
+        assert_eq!(store.get().as_deref(), Some(&b"fake session"[..]));
+        let calls = retriever.into_inner().calls();
+        let sign_in_calls: Vec<&FakeCall> = calls
+            .iter()
+            .filter(|call| !matches!(call, FakeCall::IsAuthorized | FakeCall::ExportSession))
+            .collect();
+        assert_eq!(
+            sign_in_calls,
+            [
+                &FakeCall::RequestLoginCode {
+                    phone: "+14155552671".into()
+                },
+                &FakeCall::SignIn {
+                    code: "00000".into()
+                },
+                &FakeCall::ResendLoginCode {
+                    phone: "+14155552671".into()
+                },
+                &FakeCall::SignIn {
+                    code: "12345".into()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_checks_the_password() {
+        let transport = FakeTransport::new();
+        transport.push_sign_in(Ok(SignInStep::PasswordRequired {
+            token: FakePasswordToken,
+            hint: Some("pet name".into()),
+        }));
+        transport.push_check_password(Err(Error::SignIn(SignInFailure::InvalidPassword)));
+        let store = MemorySessionStore::new();
+        let mut retriever = UserRetriever::with_transport(transport, &store);
+        let mut prompter = ScriptedPrompter {
+            phones: ["+14155552671"].into(),
+            codes: ["12345"].into(),
+            passwords: ["wrong", "right"].into(),
+            ..Default::default()
+        };
+
+        retriever.sign_in(&mut prompter).await.unwrap();
+
+        assert_eq!(
+            prompter.hints,
+            [Some("pet name".into()), Some("pet name".into())]
+        );
+        assert_eq!(prompter.errors.len(), 1);
+        assert!(store.get().is_some());
+        assert!(retriever.is_authorized().await.unwrap());
+        let calls = retriever.into_inner().calls();
+        assert!(calls.contains(&FakeCall::GetPassword));
+        assert!(calls.contains(&FakeCall::CheckPassword {
+            password: "right".into()
+        }));
+    }
+
+    fn login_token(token: &[u8], expires: i64) -> QrLoginStep<FakePasswordToken> {
+        QrLoginStep::Token {
+            token: token.to_vec(),
+            expires: Timestamp::from_unix(expires),
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_in_qr_shows_each_login_token_once() {
+        let transport = FakeTransport::new();
+        transport.push_login_token(Ok(login_token(b"first", 1_700_000_030)));
+        transport.push_login_token(Ok(login_token(b"first", 1_700_000_030)));
+        transport.push_login_token(Ok(login_token(b"second", 1_700_000_060)));
+        let store = MemorySessionStore::new();
+        let mut retriever =
+            UserRetriever::with_transport(transport, &store).with_clock(FakeClock::new());
+        let mut prompter = ScriptedPrompter::default();
+
+        retriever.sign_in_qr(&mut prompter).await.unwrap();
+
+        assert_eq!(
+            prompter.qr_logins,
+            [
+                QrLogin::new(b"first", Timestamp::from_unix(1_700_000_030)),
+                QrLogin::new(b"second", Timestamp::from_unix(1_700_000_060)),
+            ]
+        );
+        assert_eq!(prompter.qr_logins[0].url, "tg://login?token=Zmlyc3Q");
+        assert_eq!(retriever.clock.sleeps(), [QR_LOGIN_POLL_INTERVAL; 3]);
+        assert!(store.get().is_some());
+        assert!(retriever.is_authorized().await.unwrap());
+        let calls = retriever.into_inner().calls();
+        assert_eq!(
+            calls
+                .iter()
+                .filter(|call| **call == FakeCall::ExportLoginToken)
+                .count(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_qr_imports_the_login_token_to_another_dc() {
+        let transport = FakeTransport::new();
+        transport.push_login_token(Ok(login_token(b"token", 1_700_000_030)));
+        transport.push_login_token(Ok(QrLoginStep::MigrateTo {
+            dc_id: 4,
+            token: b"token".to_vec(),
+        }));
+        let store = MemorySessionStore::new();
+        let mut retriever =
+            UserRetriever::with_transport(transport, &store).with_clock(FakeClock::new());
+        let mut prompter = ScriptedPrompter::default();
+
+        retriever.sign_in_qr(&mut prompter).await.unwrap();
+
+        assert_eq!(prompter.qr_logins.len(), 1);
+        assert!(store.get().is_some());
+        let calls = retriever.into_inner().calls();
+        let login_calls: Vec<&FakeCall> = calls
+            .iter()
+            .filter(|call| !matches!(call, FakeCall::IsAuthorized | FakeCall::ExportSession))
+            .collect();
+        assert_eq!(
+            login_calls,
+            [
+                &FakeCall::ExportLoginToken,
+                &FakeCall::ExportLoginToken,
+                &FakeCall::ImportLoginToken { dc_id: 4 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_qr_checks_the_password() {
+        let transport = FakeTransport::new();
+        transport.push_login_token(Ok(login_token(b"token", 1_700_000_030)));
+        transport.push_login_token(Ok(QrLoginStep::Accepted(SignInStep::PasswordRequired {
+            token: FakePasswordToken,
+            hint: Some("pet name".into()),
+        })));
+        let store = MemorySessionStore::new();
+        let mut retriever =
+            UserRetriever::with_transport(transport, &store).with_clock(FakeClock::new());
+        let mut prompter = ScriptedPrompter {
+            passwords: ["secret"].into(),
+            ..Default::default()
+        };
+
+        retriever.sign_in_qr(&mut prompter).await.unwrap();
+
+        assert_eq!(prompter.hints, [Some("pet name".into())]);
+        assert!(store.get().is_some());
+        assert!(retriever.is_authorized().await.unwrap());
+        assert!(retriever
+            .into_inner()
+            .calls()
+            .contains(&FakeCall::CheckPassword {
+                password: "secret".into()
+            }));
+    }
+
+    #[tokio::test]
+    async fn sign_in_qr_fails_without_saving() {
+        let transport = FakeTransport::new();
+        transport.push_login_token(Ok(login_token(b"token", 1_700_000_030)));
+        transport.push_login_token(Err(Error::SignIn(SignInFailure::SignUpRequired)));
+        let store = MemorySessionStore::new();
+        let mut retriever =
+            UserRetriever::with_transport(transport, &store).with_clock(FakeClock::new());
+
+        let result = retriever.sign_in_qr(&mut ScriptedPrompter::default()).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::SignIn(SignInFailure::SignUpRequired))
+        ));
+        assert!(store.get().is_none());
+    }
+
+    #[tokio::test]
+    async fn sign_out_keeps_a_resumed_session() {
+        let store = MemorySessionStore::new();
+        store.save(b"saved by login").unwrap();
+        let transport = FakeTransport::new().authorized(true);
+        let mut retriever = UserRetriever::with_transport(transport, &store);
+
+        retriever
+            .sign_in(&mut ScriptedPrompter::default())
+            .await
+            .unwrap();
+        assert!(!retriever.sign_out().await.unwrap());
+
+        assert_eq!(store.get().as_deref(), Some(&b"saved by login"[..]));
+        assert!(!retriever.into_inner().calls().contains(&FakeCall::LogOut));
+    }
+
+    #[tokio::test]
+    async fn sign_out_logs_an_ephemeral_session_out() {
+        let store = MemorySessionStore::new();
+        let transport = FakeTransport::new();
+        let mut retriever = UserRetriever::with_transport(transport, &store).with_ephemeral(true);
+        let mut prompter = ScriptedPrompter {
+            phones: ["+14155552671"].into(),
+            codes: ["12345"].into(),
+            ..Default::default()
+        };
+
+        retriever.sign_in(&mut prompter).await.unwrap();
+        assert!(store.get().is_none());
+        assert!(retriever.sign_out().await.unwrap());
+
+        assert!(!retriever.is_authorized().await.unwrap());
+        assert!(retriever.into_inner().calls().contains(&FakeCall::LogOut));
+    }
+
+    #[tokio::test]
+    async fn sign_in_gives_up_after_the_last_attempt() {
+        let transport = FakeTransport::new();
+        for _ in 0..SIGN_IN_ATTEMPTS {
+            transport.push_sign_in(Err(Error::SignIn(SignInFailure::InvalidCode)));
+        }
+        let store = MemorySessionStore::new();
+        let mut retriever = UserRetriever::with_transport(transport, &store);
+        let mut prompter = ScriptedPrompter {
+            phones: ["+14155552671"].into(),
+            codes: ["1", "2", "3"].into(),
+            ..Default::default()
+        };
+
+        let signed_in = retriever.sign_in(&mut prompter).await;
+
+        assert!(matches!(
+            signed_in,
+            Err(Error::SignIn(SignInFailure::InvalidCode))
+        ));
+        assert!(store.get().is_none());
     }
 }
@@ -0,0 +1,340 @@
+use crate::{
+    transport::{PhotoLocation, Transport},
+    user::{Timestamp, UserData},
+    Error, Result,
+};
+use grammers_tl_types as tl;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tl::enums::InputUser;
+use tracing::debug;
+
+/// Amount of photos requested per `photos.GetUserPhotos`
+const PHOTOS_PAGE_SIZE: i32 = 100;
+/// Amount of names tried for a new evidence directory before giving up
+const RUN_DIR_ATTEMPTS: u32 = 100;
+
+/// Options controlling which profile photos of the accounts found are downloaded
+#[derive(Clone, Debug)]
+pub struct PhotoOptions {
+    /// Directory in which a new evidence directory is created for every check
+    pub evidence_dir: PathBuf,
+    /// Whether every past profile photo (`photos.GetUserPhotos`) should be downloaded, rather
+    /// than only the current one
+    pub history: bool,
+}
+
+/// A profile photo saved to the evidence directory
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadedPhoto {
+    /// The id of the photo
+    pub photo_id: i64,
+    /// When the photo was uploaded. [`None`] if the current profile photo had to be downloaded
+    /// without being listed by `photos.GetUserPhotos`
+    pub date: Option<Timestamp>,
+    /// Whether this is the current profile photo of the account
+    pub current: bool,
+    /// Where the photo was saved
+    pub path: PathBuf,
+    /// The hex-encoded SHA-256 of the saved file
+    pub sha256: String,
+}
+
+/// Creates a new directory inside `evidence_dir`, holding the photos downloaded by a single
+/// check
+///
+/// The directory is named after when the check started and the current process, with a counter
+/// appended if it already exists, so concurrent checks never share an evidence directory
+pub(crate) fn create_run_dir(evidence_dir: &Path) -> Result<PathBuf> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let name = format!(
+        "run-{}-{:09}-{}",
+        started.as_secs(),
+        started.subsec_nanos(),
+        process::id()
+    );
+    fs::create_dir_all(evidence_dir).map_err(Error::Evidence)?;
+    for attempt in 0..RUN_DIR_ATTEMPTS {
+        let run_dir = match attempt {
+            0 => evidence_dir.join(&name),
+            _ => evidence_dir.join(format!("{name}-{attempt}")),
+        };
+        match fs::create_dir(&run_dir) {
+            Ok(()) => {
+                debug!("Saving photos to '{}'", run_dir.display());
+                return Ok(run_dir);
+            }
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(Error::Evidence(e)),
+            Err(_) => debug!("'{}' already exists", run_dir.display()),
+        }
+    }
+    Err(Error::Evidence(io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("'{}' already exists", evidence_dir.join(name).display()),
+    )))
+}
+
+/// Requests the profile photos of `user`, only the first one unless `history` is set
+async fn user_photos<T: Transport>(
+    transport: &T,
+    user: &UserData,
+    history: bool,
+) -> Result<Vec<tl::types::Photo>> {
+    let limit = if history { PHOTOS_PAGE_SIZE } else { 1 };
+    let mut photos = Vec::new();
+    loop {
+        let offset = i32::try_from(photos.len()).unwrap_or(i32::MAX);
+        let page = transport
+            .get_user_photos(InputUser::from(user), offset, limit)
+            .await?;
+        let last_page = !history || usize::try_from(limit).is_ok_and(|limit| page.len() < limit);
+        photos.extend(page);
+        if last_page {
+            return Ok(photos);
+        }
+    }
+}
+
+/// Downloads the profile photos of `user` into `run_dir/<user id>/` (see [`PhotoOptions`])
+pub(crate) async fn download_photos<T: Transport>(
+    transport: &T,
+    user: &UserData,
+    history: bool,
+    run_dir: &Path,
+) -> Result<Vec<DownloadedPhoto>> {
+    let current_id = user.photo.as_ref().map(|photo| photo.photo_id);
+    if current_id.is_none() && !history {
+        return Ok(Vec::new());
+    }
+    let photos: Vec<tl::types::Photo> = user_photos(transport, user, history)
+        .await?
+        .into_iter()
+        .filter(|photo| history || Some(photo.id) == current_id)
+        .collect();
+    if photos.is_empty() && current_id.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let user_dir = run_dir.join(user.id.to_string());
+    fs::create_dir_all(&user_dir).map_err(Error::Evidence)?;
+
+    let mut downloaded = Vec::with_capacity(photos.len());
+    for photo in photos {
+        let (photo_id, date) = (photo.id, photo.date);
+        let bytes = transport.download(PhotoLocation::Photo(photo)).await?;
+        downloaded.push(save(
+            &user_dir,
+            photo_id,
+            Some(date.into()),
+            Some(photo_id) == current_id,
+            &bytes,
+        )?);
+    }
+
+    // The current profile photo is not listed if it was set by the requesting user
+    if let Some(current) = user
+        .photo
+        .as_ref()
+        .filter(|_| !downloaded.iter().any(|p| p.current))
+    {
+        let bytes = transport
+            .download(PhotoLocation::ProfilePhoto {
+                user_id: user.id,
+                access_hash: user.access_hash.unwrap_or(0),
+                photo_id: current.photo_id,
+                dc_id: current.dc_id,
+            })
+            .await?;
+        downloaded.insert(0, save(&user_dir, current.photo_id, None, true, &bytes)?);
+    }
+
+    debug!("Downloaded {} photos of {}", downloaded.len(), user.id);
+    Ok(downloaded)
+}
+
+/// Writes `bytes` to `dir/<photo id>.jpg`
+fn save(
+    dir: &Path,
+    photo_id: i64,
+    date: Option<Timestamp>,
+    current: bool,
+    bytes: &[u8],
+) -> Result<DownloadedPhoto> {
+    let path = dir.join(format!("{photo_id}.jpg"));
+    fs::write(&path, bytes).map_err(Error::Evidence)?;
+    Ok(DownloadedPhoto {
+        photo_id,
+        date,
+        current,
+        path,
+        sha256: format!("{:x}", Sha256::digest(bytes)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transport::{FakeCall, FakeTransport},
+        user::UserProfilePhoto,
+    };
+
+    /// SHA-256 of the bytes [`FakeTransport`] downloads for the photo `2`
+    const PHOTO_2_SHA256: &str = "d4735e3a265e16eee03f59718b9b5d03019c07d8b6c51f90da3a666eec13ab35";
+
+    fn photo(id: i64) -> tl::types::Photo {
+        tl::types::Photo {
+            has_stickers: false,
+            id,
+            access_hash: 0,
+            file_reference: Vec::new(),
+            date: 1_700_000_000 + i32::try_from(id).unwrap(),
+            sizes: Vec::new(),
+            video_sizes: None,
+            dc_id: 2,
+        }
+    }
+
+    fn user(photo_id: Option<i64>) -> UserData {
+        UserData {
+            id: 10,
+            access_hash: Some(1),
+            photo: photo_id.map(|photo_id| UserProfilePhoto {
+                has_video: false,
+                personal: false,
+                photo_id,
+                stripped_thumb: None,
+                dc_id: 2,
+                thumbnail: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_the_current_photo() {
+        let run_dir = tempfile::tempdir().unwrap();
+        let transport = FakeTransport::new().with_user_photos(10, vec![photo(2), photo(1)]);
+
+        let downloaded = download_photos(&transport, &user(Some(2)), false, run_dir.path())
+            .await
+            .unwrap();
+
+        let path = run_dir.path().join("10").join("2.jpg");
+        assert_eq!(
+            downloaded,
+            [DownloadedPhoto {
+                photo_id: 2,
+                date: Some(Timestamp::from_unix(1_700_000_002)),
+                current: true,
+                path: path.clone(),
+                sha256: PHOTO_2_SHA256.to_string(),
+            }]
+        );
+        assert_eq!(fs::read(path).unwrap(), b"2");
+        assert_eq!(
+            transport.calls(),
+            [
+                FakeCall::GetUserPhotos {
+                    user_id: 10,
+                    offset: 0
+                },
+                FakeCall::Download { photo_id: 2 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_the_photo_history_page_by_page() {
+        let run_dir = tempfile::tempdir().unwrap();
+        let photos = (1..=PHOTOS_PAGE_SIZE + 1)
+            .map(|id| photo(id.into()))
+            .collect();
+        let transport = FakeTransport::new().with_user_photos(10, photos);
+
+        let downloaded = download_photos(&transport, &user(Some(1)), true, run_dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(downloaded.len(), 101);
+        assert_eq!(downloaded.iter().filter(|p| p.current).count(), 1);
+        assert!(downloaded[0].current);
+        assert_eq!(downloaded[1].sha256, PHOTO_2_SHA256);
+        assert_eq!(
+            fs::read_dir(run_dir.path().join("10")).unwrap().count(),
+            101
+        );
+        let pages: Vec<FakeCall> = transport
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, FakeCall::GetUserPhotos { .. }))
+            .collect();
+        assert_eq!(
+            pages,
+            [
+                FakeCall::GetUserPhotos {
+                    user_id: 10,
+                    offset: 0
+                },
+                FakeCall::GetUserPhotos {
+                    user_id: 10,
+                    offset: 100
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_an_unlisted_current_photo() {
+        let run_dir = tempfile::tempdir().unwrap();
+        let transport = FakeTransport::new();
+
+        let downloaded = download_photos(&transport, &user(Some(2)), false, run_dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(downloaded.len(), 1);
+        assert_eq!(downloaded[0].date, None);
+        assert!(downloaded[0].current);
+        assert_eq!(downloaded[0].sha256, PHOTO_2_SHA256);
+    }
+
+    #[tokio::test]
+    async fn skips_accounts_without_a_photo() {
+        let run_dir = tempfile::tempdir().unwrap();
+        let transport = FakeTransport::new();
+
+        let downloaded = download_photos(&transport, &user(None), false, run_dir.path())
+            .await
+            .unwrap();
+
+        assert!(downloaded.is_empty());
+        assert!(transport.calls().is_empty());
+        assert!(!run_dir.path().join("10").exists());
+    }
+
+    #[test]
+    fn run_dirs_are_never_shared() {
+        let evidence_dir = tempfile::tempdir().unwrap();
+        let evidence_dir = evidence_dir.path().join("evidence");
+
+        let run_dirs: Vec<PathBuf> = (0..10)
+            .map(|_| create_run_dir(&evidence_dir).unwrap())
+            .collect();
+
+        for (i, run_dir) in run_dirs.iter().enumerate() {
+            assert!(run_dir.is_dir());
+            assert_eq!(run_dir.parent(), Some(evidence_dir.as_path()));
+            assert!(!run_dirs[i + 1..].contains(run_dir));
+        }
+    }
+}
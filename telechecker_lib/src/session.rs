@@ -0,0 +1,480 @@
+use crate::{Error, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+/// Prefix of the sessions encrypted by [`EncryptedSessionStore`], followed by the format version
+const ENCRYPTED_HEADER: [u8; 5] = *b"TCSE\x01";
+/// Length of the salt the key of an [`EncryptedSessionStore`] is derived with
+const SALT_LEN: usize = 16;
+/// Length of the nonce of ChaCha20-Poly1305
+const NONCE_LEN: usize = 12;
+
+/// Where the session (the authorization of the requesting user) is loaded from when connecting
+/// and saved to after signing in (see [`crate::UserRetriever::connect`])
+///
+/// Implemented for [`FileSessionStore`], [`MemorySessionStore`] and [`StringSessionStore`], any
+/// of which can be wrapped in an [`EncryptedSessionStore`], and can be implemented to keep
+/// sessions in other storage
+pub trait SessionStore: Send + Sync {
+    /// Returns the saved session, or [`None`] if none has been saved yet
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Session`] if the session cannot be read
+    fn load(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Saves `session`, replacing the one saved before
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Session`] if the session cannot be written
+    fn save(&self, session: &[u8]) -> Result<()>;
+
+    /// Removes the saved session, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Session`] if the session cannot be removed
+    fn clear(&self) -> Result<()>;
+}
+
+impl<S: SessionStore + ?Sized> SessionStore for &S {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        (**self).load()
+    }
+
+    fn save(&self, session: &[u8]) -> Result<()> {
+        (**self).save(session)
+    }
+
+    fn clear(&self) -> Result<()> {
+        (**self).clear()
+    }
+}
+
+impl<S: SessionStore + ?Sized> SessionStore for Box<S> {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        (**self).load()
+    }
+
+    fn save(&self, session: &[u8]) -> Result<()> {
+        (**self).save(session)
+    }
+
+    fn clear(&self) -> Result<()> {
+        (**self).clear()
+    }
+}
+
+/// [`SessionStore`] keeping the session in a file, as telechecker always has (see
+/// [`crate::SESSION_FILE`])
+#[derive(Clone, Debug)]
+pub struct FileSessionStore {
+    path: PathBuf,
+}
+
+impl FileSessionStore {
+    /// Returns a new [`FileSessionStore`] keeping the session at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            // older releases created the session file empty before signing in
+            Ok(session) if session.is_empty() => Ok(None),
+            Ok(session) => Ok(Some(session)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Session(e)),
+        }
+    }
+
+    /// Writes the session next to the file, then replaces the file with it, so a crash while
+    /// saving never leaves a truncated session behind
+    fn save(&self, session: &[u8]) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, session).map_err(Error::Session)?;
+        fs::rename(&tmp, &self.path).map_err(Error::Session)
+    }
+
+    fn clear(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::Session(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// [`SessionStore`] keeping the session in memory only, so nothing is left on disk once the
+/// process exits
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    session: Mutex<Option<Vec<u8>>>,
+}
+
+impl MemorySessionStore {
+    /// Returns a new, empty [`MemorySessionStore`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the session saved last, if any
+    #[must_use]
+    pub fn get(&self) -> Option<Vec<u8>> {
+        self.session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.get())
+    }
+
+    fn save(&self, session: &[u8]) -> Result<()> {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = Some(session.to_vec());
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+}
+
+/// [`SessionStore`] keeping the session as a base64 string, which can be moved between machines
+/// or kept in an environment variable or a database
+#[derive(Debug, Default)]
+pub struct StringSessionStore {
+    session: Mutex<Option<String>>,
+}
+
+impl StringSessionStore {
+    /// Returns a new [`StringSessionStore`] holding `session`, as previously returned by
+    /// [`StringSessionStore::get`]
+    #[must_use]
+    pub const fn new(session: Option<String>) -> Self {
+        Self {
+            session: Mutex::new(session),
+        }
+    }
+
+    /// Returns the session saved last as a base64 string, if any
+    #[must_use]
+    pub fn get(&self) -> Option<String> {
+        self.session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl SessionStore for StringSessionStore {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        self.get()
+            .map(|session| {
+                STANDARD
+                    .decode(session.trim())
+                    .map_err(|e| Error::Session(io::Error::new(ErrorKind::InvalidData, e)))
+            })
+            .transpose()
+    }
+
+    fn save(&self, session: &[u8]) -> Result<()> {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(STANDARD.encode(session));
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+}
+
+/// Function that returns whether `session` was encrypted by an [`EncryptedSessionStore`]
+#[must_use]
+pub fn is_encrypted(session: &[u8]) -> bool {
+    session.starts_with(&ENCRYPTED_HEADER)
+}
+
+/// [`SessionStore`] encrypting the session kept in another [`SessionStore`] with a passphrase
+///
+/// The key is derived from the passphrase with Argon2id and a random salt, and the session is
+/// encrypted with ChaCha20-Poly1305, so a session that was tampered with is rejected rather than
+/// loaded. Sessions that are not encrypted yet are rejected too, see
+/// [`EncryptedSessionStore::migrate`]
+pub struct EncryptedSessionStore<S> {
+    inner: S,
+    passphrase: String,
+}
+
+impl<S: SessionStore> EncryptedSessionStore<S> {
+    /// Returns a new [`EncryptedSessionStore`] keeping the session in `inner`, encrypted with
+    /// `passphrase`
+    pub fn new(inner: S, passphrase: impl Into<String>) -> Self {
+        Self {
+            inner,
+            passphrase: passphrase.into(),
+        }
+    }
+
+    /// Encrypts the session kept in the inner [`SessionStore`] if it is not encrypted yet (i.e.
+    /// it was saved by an earlier release). Returns whether the session was migrated
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the session cannot be loaded or saved
+    pub fn migrate(&self) -> Result<bool> {
+        match self.inner.load()? {
+            Some(session) if !is_encrypted(&session) => {
+                self.save(&session)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Derives the key the session is encrypted with from the passphrase and `salt`
+    fn key(&self, salt: &[u8]) -> Result<Key> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|_| Error::SessionEncryption)?;
+        Ok(key)
+    }
+}
+
+impl<S: SessionStore> SessionStore for EncryptedSessionStore<S> {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        let Some(encrypted) = self.inner.load()? else {
+            return Ok(None);
+        };
+        let Some(body) = encrypted.strip_prefix(&ENCRYPTED_HEADER) else {
+            return Err(Error::UnencryptedSession);
+        };
+        if body.len() < SALT_LEN + NONCE_LEN {
+            return Err(Error::SessionEncryption);
+        }
+        let (salt, body) = body.split_at(SALT_LEN);
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &ENCRYPTED_HEADER,
+        };
+        ChaCha20Poly1305::new(&self.key(salt)?)
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map(Some)
+            .map_err(|_| Error::SessionEncryption)
+    }
+
+    fn save(&self, session: &[u8]) -> Result<()> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: session,
+            aad: &ENCRYPTED_HEADER,
+        };
+        let ciphertext = ChaCha20Poly1305::new(&self.key(&salt)?)
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::SessionEncryption)?;
+
+        let mut encrypted =
+            Vec::with_capacity(ENCRYPTED_HEADER.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+        encrypted.extend_from_slice(&ENCRYPTED_HEADER);
+        encrypted.extend_from_slice(&salt);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        self.inner.save(&encrypted)
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.inner.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &[u8] = b"auth key and data center";
+
+    fn encrypted(inner: &MemorySessionStore) -> Vec<u8> {
+        EncryptedSessionStore::new(inner, "passphrase")
+            .save(SESSION)
+            .unwrap();
+        inner.get().unwrap()
+    }
+
+    #[test]
+    fn encrypted_session_round_trips() {
+        let inner = MemorySessionStore::new();
+        let store = EncryptedSessionStore::new(&inner, "passphrase");
+        assert_eq!(store.load().unwrap(), None);
+
+        store.save(SESSION).unwrap();
+
+        let saved = inner.get().unwrap();
+        assert!(is_encrypted(&saved));
+        assert!(!saved.windows(SESSION.len()).any(|w| w == SESSION));
+        assert_eq!(store.load().unwrap().as_deref(), Some(SESSION));
+        // every save uses a new salt and nonce
+        store.save(SESSION).unwrap();
+        assert_ne!(inner.get().unwrap(), saved);
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let inner = MemorySessionStore::new();
+        encrypted(&inner);
+
+        let store = EncryptedSessionStore::new(&inner, "wrong passphrase");
+
+        assert!(matches!(store.load(), Err(Error::SessionEncryption)));
+    }
+
+    #[test]
+    fn rejects_a_tampered_session() {
+        let header = ENCRYPTED_HEADER.len();
+        let inner = MemorySessionStore::new();
+        let saved = encrypted(&inner);
+        for (what, index) in [
+            ("salt", header),
+            ("nonce", header + SALT_LEN),
+            ("ciphertext", header + SALT_LEN + NONCE_LEN),
+            ("tag", saved.len() - 1),
+        ] {
+            let mut tampered = saved.clone();
+            tampered[index] ^= 1;
+            inner.save(&tampered).unwrap();
+
+            let loaded = EncryptedSessionStore::new(&inner, "passphrase").load();
+
+            assert!(matches!(loaded, Err(Error::SessionEncryption)), "{what}");
+        }
+
+        inner.save(&saved[..header + SALT_LEN]).unwrap();
+        let loaded = EncryptedSessionStore::new(&inner, "passphrase").load();
+        assert!(matches!(loaded, Err(Error::SessionEncryption)));
+    }
+
+    #[test]
+    fn rejects_an_unencrypted_session() {
+        let inner = MemorySessionStore::new();
+        inner.save(SESSION).unwrap();
+
+        let loaded = EncryptedSessionStore::new(&inner, "passphrase").load();
+
+        assert!(matches!(loaded, Err(Error::UnencryptedSession)));
+    }
+
+    #[test]
+    fn migrates_an_unencrypted_session_once() {
+        let inner = MemorySessionStore::new();
+        let store = EncryptedSessionStore::new(&inner, "passphrase");
+        assert!(!store.migrate().unwrap());
+        inner.save(SESSION).unwrap();
+
+        assert!(store.migrate().unwrap());
+
+        let migrated = inner.get().unwrap();
+        assert!(is_encrypted(&migrated));
+        assert_eq!(store.load().unwrap().as_deref(), Some(SESSION));
+        assert!(!store.migrate().unwrap());
+        assert_eq!(inner.get().unwrap(), migrated);
+    }
+
+    #[test]
+    fn file_session_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::SESSION_FILE);
+        let store = FileSessionStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        store.save(b"first").unwrap();
+        store.save(SESSION).unwrap();
+
+        assert_eq!(store.load().unwrap().as_deref(), Some(SESSION));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        store.clear().unwrap();
+        assert!(!path.exists());
+        assert_eq!(store.load().unwrap(), None);
+        store.clear().unwrap();
+    }
+
+    #[test]
+    fn file_session_ignores_an_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::SESSION_FILE);
+        fs::write(&path, b"").unwrap();
+
+        assert_eq!(FileSessionStore::new(&path).load().unwrap(), None);
+    }
+
+    #[test]
+    fn file_session_is_replaced_by_an_encrypted_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::SESSION_FILE);
+        fs::write(&path, SESSION).unwrap();
+        let store = EncryptedSessionStore::new(FileSessionStore::new(&path), "passphrase");
+
+        assert!(store.migrate().unwrap());
+
+        assert!(is_encrypted(&fs::read(&path).unwrap()));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert_eq!(store.load().unwrap().as_deref(), Some(SESSION));
+    }
+
+    #[test]
+    fn string_session_round_trips() {
+        let store = StringSessionStore::new(None);
+        assert_eq!(store.load().unwrap(), None);
+
+        store.save(SESSION).unwrap();
+
+        let saved = store.get().unwrap();
+        assert_eq!(saved, STANDARD.encode(SESSION));
+        // e.g. read from an environment variable with a trailing newline
+        let store = StringSessionStore::new(Some(format!("{saved}\n")));
+        assert_eq!(store.load().unwrap().as_deref(), Some(SESSION));
+        store.clear().unwrap();
+        assert_eq!(store.get(), None);
+    }
+
+    #[test]
+    fn string_session_rejects_invalid_base64() {
+        let store = StringSessionStore::new(Some("not base64!".to_string()));
+
+        assert!(
+            matches!(store.load(), Err(Error::Session(e)) if e.kind() == ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn detects_encrypted_sessions() {
+        let inner = MemorySessionStore::new();
+
+        assert!(is_encrypted(&encrypted(&inner)));
+        assert!(is_encrypted(b"TCSE\x01"));
+        assert!(!is_encrypted(b"TCSE\x02 a later format"));
+        assert!(!is_encrypted(SESSION));
+        assert!(!is_encrypted(b""));
+    }
+}
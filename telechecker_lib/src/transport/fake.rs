@@ -0,0 +1,587 @@
+use super::{ConnectParams, PhotoLocation, QrLoginStep, SignInStep, Transport};
+use crate::{
+    auth::{CodeDelivery, SentCode},
+    Error, Result,
+};
+use grammers_tl_types as tl;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use tl::{
+    enums::{InputContact, InputUser},
+    types::{
+        contacts::{Contacts, ImportedContacts, ResolvedPeer},
+        UserFull,
+    },
+};
+
+/// A request received by a [`FakeTransport`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FakeCall {
+    /// [`Transport::is_authorized`]
+    IsAuthorized,
+    /// [`Transport::request_login_code`]
+    RequestLoginCode {
+        /// The phone number the code was requested for
+        phone: String,
+    },
+    /// [`Transport::resend_login_code`]
+    ResendLoginCode {
+        /// The phone number the code was sent again to
+        phone: String,
+    },
+    /// [`Transport::sign_in`]
+    SignIn {
+        /// The code provided
+        code: String,
+    },
+    /// [`Transport::get_password`]
+    GetPassword,
+    /// [`Transport::check_password`]
+    CheckPassword {
+        /// The password provided
+        password: String,
+    },
+    /// [`Transport::export_login_token`]
+    ExportLoginToken,
+    /// [`Transport::import_login_token`]
+    ImportLoginToken {
+        /// The data center the login token was imported to
+        dc_id: i32,
+    },
+    /// [`Transport::log_out`]
+    LogOut,
+    /// [`Transport::import_contacts`]
+    ImportContacts {
+        /// The phone numbers imported
+        phones: Vec<String>,
+    },
+    /// [`Transport::delete_contacts`]
+    DeleteContacts {
+        /// The ids of the accounts deleted
+        user_ids: Vec<i64>,
+    },
+    /// [`Transport::get_contacts`]
+    GetContacts,
+    /// [`Transport::resolve_phone`]
+    ResolvePhone {
+        /// The phone number requested
+        phone: String,
+    },
+    /// [`Transport::resolve_username`]
+    ResolveUsername {
+        /// The username requested
+        username: String,
+    },
+    /// [`Transport::get_users`]
+    GetUsers {
+        /// The ids of the accounts requested
+        user_ids: Vec<i64>,
+    },
+    /// [`Transport::get_full_user`]
+    GetFullUser {
+        /// The id of the account requested
+        user_id: i64,
+    },
+    /// [`Transport::get_user_photos`]
+    GetUserPhotos {
+        /// The id of the account requested
+        user_id: i64,
+        /// The amount of photos skipped
+        offset: i32,
+    },
+    /// [`Transport::download`]
+    Download {
+        /// The id of the photo downloaded
+        photo_id: i64,
+    },
+    /// [`Transport::export_session`]
+    ExportSession,
+}
+
+/// The [`Transport::LoginToken`] of a [`FakeTransport`]
+pub struct FakeLoginToken {
+    /// The phone number the code was requested for
+    pub phone: String,
+}
+
+/// The [`Transport::PasswordToken`] of a [`FakeTransport`]
+pub struct FakePasswordToken;
+
+/// An in-memory [`Transport`] answering with scripted responses, used to test the library without
+/// a telegram account
+///
+/// Requests without a scripted response succeed with an empty answer. The accounts returned by
+/// `contacts.ImportContacts` are added to a fake address book, which `contacts.GetContacts` and
+/// `contacts.DeleteContacts` operate on, and `users.GetUsers` answers with the accounts in the
+/// address book or added with [`FakeTransport::with_user`], which `contacts.ResolvePhone` and
+/// `contacts.ResolveUsername` also search by phone number and username. Every request is recorded (see [`FakeTransport::calls`])
+#[derive(Default)]
+pub struct FakeTransport {
+    authorized: Mutex<bool>,
+    sign_ins: Mutex<VecDeque<Result<SignInStep<FakePasswordToken>>>>,
+    passwords: Mutex<VecDeque<Result<()>>>,
+    login_tokens: Mutex<VecDeque<Result<QrLoginStep<FakePasswordToken>>>>,
+    /// Scripted imports, along with whether their response is lost after they were applied
+    imports: Mutex<VecDeque<(Result<ImportedContacts>, bool)>>,
+    contacts: Mutex<Vec<tl::types::User>>,
+    users: Mutex<Vec<tl::types::User>>,
+    full_users: Mutex<Vec<UserFull>>,
+    photos: Mutex<HashMap<i64, Vec<tl::types::Photo>>>,
+    calls: Mutex<Vec<FakeCall>>,
+}
+
+/// Locks `mutex`, ignoring poisoning since the fake holds no invariants across panics
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl FakeTransport {
+    /// Returns a new, unauthorized [`FakeTransport`] with an empty address book
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the session is already authorized
+    #[must_use]
+    pub fn authorized(self, authorized: bool) -> Self {
+        *lock(&self.authorized) = authorized;
+        self
+    }
+
+    /// Adds `user` to the fake address book
+    #[must_use]
+    pub fn with_contact(self, user: tl::types::User) -> Self {
+        lock(&self.contacts).push(user);
+        self
+    }
+
+    /// Adds `user` to the accounts returned by [`Transport::get_users`], without adding it to the
+    /// fake address book. Takes precedence over the address book for the same account
+    #[must_use]
+    pub fn with_user(self, user: tl::types::User) -> Self {
+        lock(&self.users).push(user);
+        self
+    }
+
+    /// Sets the response to [`Transport::get_full_user`] for the account `full_user.id`. Other
+    /// accounts are answered with `USER_ID_INVALID`
+    #[must_use]
+    pub fn with_full_user(self, full_user: UserFull) -> Self {
+        lock(&self.full_users).push(full_user);
+        self
+    }
+
+    /// Sets the photos returned by [`Transport::get_user_photos`] for the account `user_id`.
+    /// Downloading any photo returns its id as text
+    #[must_use]
+    pub fn with_user_photos(self, user_id: i64, photos: Vec<tl::types::Photo>) -> Self {
+        lock(&self.photos).insert(user_id, photos);
+        self
+    }
+
+    /// Scripts the response to the next [`Transport::sign_in`]
+    pub fn push_sign_in(&self, response: Result<SignInStep<FakePasswordToken>>) {
+        lock(&self.sign_ins).push_back(response);
+    }
+
+    /// Scripts the response to the next [`Transport::check_password`]
+    pub fn push_check_password(&self, response: Result<()>) {
+        lock(&self.passwords).push_back(response);
+    }
+
+    /// Scripts the response to the next [`Transport::export_login_token`] or
+    /// [`Transport::import_login_token`]
+    pub fn push_login_token(&self, response: Result<QrLoginStep<FakePasswordToken>>) {
+        lock(&self.login_tokens).push_back(response);
+    }
+
+    /// Scripts the response to the next [`Transport::import_contacts`]
+    pub fn push_import(&self, response: Result<ImportedContacts>) {
+        lock(&self.imports).push_back((response, false));
+    }
+
+    /// Scripts the next [`Transport::import_contacts`] to add the accounts of `response` to the
+    /// fake address book, then fail as if the connection was lost before the response arrived
+    pub fn push_lost_import(&self, response: ImportedContacts) {
+        lock(&self.imports).push_back((Ok(response), true));
+    }
+
+    /// Returns every request received so far
+    #[must_use]
+    pub fn calls(&self) -> Vec<FakeCall> {
+        lock(&self.calls).clone()
+    }
+
+    /// Returns the ids of the accounts currently in the fake address book
+    #[must_use]
+    pub fn contact_ids(&self) -> Vec<i64> {
+        lock(&self.contacts).iter().map(|u| u.id).collect()
+    }
+
+    /// Returns a [`tl::types::User`] with only its `id`, `access_hash` and `phone` set
+    #[must_use]
+    pub const fn user(id: i64, phone: Option<String>) -> tl::types::User {
+        tl::types::User {
+            is_self: false,
+            contact: false,
+            mutual_contact: false,
+            deleted: false,
+            bot: false,
+            bot_chat_history: false,
+            bot_nochats: false,
+            verified: false,
+            restricted: false,
+            min: false,
+            bot_inline_geo: false,
+            support: false,
+            scam: false,
+            apply_min_photo: false,
+            fake: false,
+            bot_attach_menu: false,
+            premium: false,
+            attach_menu_enabled: false,
+            bot_can_edit: false,
+            close_friend: false,
+            stories_hidden: false,
+            stories_unavailable: false,
+            id,
+            access_hash: Some(id),
+            first_name: None,
+            last_name: None,
+            username: None,
+            phone,
+            photo: None,
+            status: None,
+            bot_info_version: None,
+            restriction_reason: None,
+            bot_inline_placeholder: None,
+            lang_code: None,
+            emoji_status: None,
+            usernames: None,
+            stories_max_id: None,
+            color: None,
+            background_emoji_id: None,
+        }
+    }
+
+    fn record(&self, call: FakeCall) {
+        lock(&self.calls).push(call);
+    }
+
+    /// Pops the next scripted login token response, accepting the login token if there is none
+    fn login_token_step(&self) -> Result<QrLoginStep<FakePasswordToken>> {
+        let step = lock(&self.login_tokens)
+            .pop_front()
+            .unwrap_or(Ok(QrLoginStep::Accepted(SignInStep::Complete)))?;
+        if matches!(step, QrLoginStep::Accepted(SignInStep::Complete)) {
+            *lock(&self.authorized) = true;
+        }
+        Ok(step)
+    }
+}
+
+impl Transport for FakeTransport {
+    type LoginToken = FakeLoginToken;
+    type PasswordToken = FakePasswordToken;
+
+    async fn is_authorized(&self) -> Result<bool> {
+        self.record(FakeCall::IsAuthorized);
+        Ok(*lock(&self.authorized))
+    }
+
+    async fn request_login_code(
+        &mut self,
+        phone: &str,
+        _params: &ConnectParams,
+    ) -> Result<(FakeLoginToken, SentCode)> {
+        self.record(FakeCall::RequestLoginCode {
+            phone: phone.to_string(),
+        });
+        let token = FakeLoginToken {
+            phone: phone.to_string(),
+        };
+        let sent_code = SentCode {
+            delivery: CodeDelivery::App,
+            next: Some(CodeDelivery::Sms),
+        };
+        Ok((token, sent_code))
+    }
+
+    async fn resend_login_code(
+        &self,
+        token: &FakeLoginToken,
+    ) -> Result<(FakeLoginToken, SentCode)> {
+        self.record(FakeCall::ResendLoginCode {
+            phone: token.phone.clone(),
+        });
+        let token = FakeLoginToken {
+            phone: token.phone.clone(),
+        };
+        let sent_code = SentCode {
+            delivery: CodeDelivery::Sms,
+            next: None,
+        };
+        Ok((token, sent_code))
+    }
+
+    async fn sign_in(
+        &self,
+        _token: &FakeLoginToken,
+        code: &str,
+    ) -> Result<SignInStep<FakePasswordToken>> {
+        self.record(FakeCall::SignIn {
+            code: code.to_string(),
+        });
+        let step = lock(&self.sign_ins)
+            .pop_front()
+            .unwrap_or(Ok(SignInStep::Complete))?;
+        if matches!(step, SignInStep::Complete) {
+            *lock(&self.authorized) = true;
+        }
+        Ok(step)
+    }
+
+    async fn get_password(&self) -> Result<FakePasswordToken> {
+        self.record(FakeCall::GetPassword);
+        Ok(FakePasswordToken)
+    }
+
+    async fn check_password(&self, _token: FakePasswordToken, password: &str) -> Result<()> {
+        self.record(FakeCall::CheckPassword {
+            password: password.to_string(),
+        });
+        lock(&self.passwords).pop_front().unwrap_or(Ok(()))?;
+        *lock(&self.authorized) = true;
+        Ok(())
+    }
+
+    async fn export_login_token(
+        &self,
+        _params: &ConnectParams,
+    ) -> Result<QrLoginStep<FakePasswordToken>> {
+        self.record(FakeCall::ExportLoginToken);
+        self.login_token_step()
+    }
+
+    async fn import_login_token(
+        &mut self,
+        dc_id: i32,
+        _token: Vec<u8>,
+        _params: &ConnectParams,
+    ) -> Result<QrLoginStep<FakePasswordToken>> {
+        self.record(FakeCall::ImportLoginToken { dc_id });
+        self.login_token_step()
+    }
+
+    async fn log_out(&self) -> Result<()> {
+        self.record(FakeCall::LogOut);
+        *lock(&self.authorized) = false;
+        Ok(())
+    }
+
+    async fn import_contacts(&self, contacts: Vec<InputContact>) -> Result<ImportedContacts> {
+        self.record(FakeCall::ImportContacts {
+            phones: contacts
+                .into_iter()
+                .map(|InputContact::InputPhoneContact(c)| c.phone)
+                .collect(),
+        });
+        let (imported, lost) = lock(&self.imports).pop_front().unwrap_or_else(|| {
+            let imported = ImportedContacts {
+                imported: Vec::new(),
+                popular_invites: Vec::new(),
+                retry_contacts: Vec::new(),
+                users: Vec::new(),
+            };
+            (Ok(imported), false)
+        });
+        let imported = imported?;
+        {
+            let mut book = lock(&self.contacts);
+            for user in &imported.users {
+                if let tl::enums::User::User(user) = user {
+                    if !book.iter().any(|u| u.id == user.id) {
+                        book.push(user.clone());
+                    }
+                }
+            }
+        }
+        if lost {
+            return Err(Error::UnexpectedResponse("connection lost"));
+        }
+        Ok(imported)
+    }
+
+    async fn delete_contacts(&self, users: Vec<InputUser>) -> Result<()> {
+        let user_ids: Vec<i64> = users
+            .into_iter()
+            .filter_map(|u| match u {
+                InputUser::User(u) => Some(u.user_id),
+                _ => None,
+            })
+            .collect();
+        lock(&self.contacts).retain(|u| !user_ids.contains(&u.id));
+        self.record(FakeCall::DeleteContacts { user_ids });
+        Ok(())
+    }
+
+    async fn get_contacts(&self) -> Result<Contacts> {
+        self.record(FakeCall::GetContacts);
+        let book = lock(&self.contacts);
+        Ok(Contacts {
+            contacts: book
+                .iter()
+                .map(|u| {
+                    tl::enums::Contact::Contact(tl::types::Contact {
+                        user_id: u.id,
+                        mutual: u.mutual_contact,
+                    })
+                })
+                .collect(),
+            saved_count: 0,
+            users: book.iter().cloned().map(tl::enums::User::User).collect(),
+        })
+    }
+
+    async fn resolve_phone(&self, phone: &str) -> Result<ResolvedPeer> {
+        self.record(FakeCall::ResolvePhone {
+            phone: phone.to_string(),
+        });
+        let phone = phone.trim_start_matches('+');
+        let user = {
+            let known = lock(&self.users);
+            let book = lock(&self.contacts);
+            known
+                .iter()
+                .chain(book.iter())
+                .find(|u| u.phone.as_deref() == Some(phone))
+                .cloned()
+        };
+        let user = user.ok_or_else(|| Error::Rpc {
+            code: 400,
+            name: "PHONE_NOT_OCCUPIED".to_string(),
+            value: None,
+        })?;
+        Ok(ResolvedPeer {
+            peer: tl::types::PeerUser { user_id: user.id }.into(),
+            chats: Vec::new(),
+            users: vec![tl::enums::User::User(user)],
+        })
+    }
+
+    async fn resolve_username(&self, username: &str) -> Result<ResolvedPeer> {
+        self.record(FakeCall::ResolveUsername {
+            username: username.to_string(),
+        });
+        let user = {
+            let known = lock(&self.users);
+            let book = lock(&self.contacts);
+            known
+                .iter()
+                .chain(book.iter())
+                .find(|u| {
+                    u.username
+                        .as_deref()
+                        .is_some_and(|u| u.eq_ignore_ascii_case(username))
+                })
+                .cloned()
+        };
+        let user = user.ok_or_else(|| Error::Rpc {
+            code: 400,
+            name: "USERNAME_NOT_OCCUPIED".to_string(),
+            value: None,
+        })?;
+        Ok(ResolvedPeer {
+            peer: tl::types::PeerUser { user_id: user.id }.into(),
+            chats: Vec::new(),
+            users: vec![tl::enums::User::User(user)],
+        })
+    }
+
+    async fn get_users(&self, users: Vec<InputUser>) -> Result<Vec<tl::enums::User>> {
+        let user_ids: Vec<i64> = users
+            .into_iter()
+            .filter_map(|u| match u {
+                InputUser::User(u) => Some(u.user_id),
+                _ => None,
+            })
+            .collect();
+        self.record(FakeCall::GetUsers {
+            user_ids: user_ids.clone(),
+        });
+        let known = lock(&self.users);
+        let book = lock(&self.contacts);
+        Ok(user_ids
+            .into_iter()
+            .map(|id| {
+                known
+                    .iter()
+                    .chain(book.iter())
+                    .find(|u| u.id == id)
+                    .cloned()
+                    .map_or(
+                        tl::enums::User::Empty(tl::types::UserEmpty { id }),
+                        tl::enums::User::User,
+                    )
+            })
+            .collect())
+    }
+
+    async fn get_full_user(&self, user: InputUser) -> Result<UserFull> {
+        let user_id = match user {
+            InputUser::User(u) => u.user_id,
+            _ => 0,
+        };
+        self.record(FakeCall::GetFullUser { user_id });
+        lock(&self.full_users)
+            .iter()
+            .find(|full_user| full_user.id == user_id)
+            .cloned()
+            .ok_or_else(|| Error::Rpc {
+                code: 400,
+                name: "USER_ID_INVALID".to_string(),
+                value: None,
+            })
+    }
+
+    async fn get_user_photos(
+        &self,
+        user: InputUser,
+        offset: i32,
+        limit: i32,
+    ) -> Result<Vec<tl::types::Photo>> {
+        let user_id = match user {
+            InputUser::User(u) => u.user_id,
+            _ => 0,
+        };
+        self.record(FakeCall::GetUserPhotos { user_id, offset });
+        Ok(lock(&self.photos)
+            .get(&user_id)
+            .map(|photos| {
+                photos
+                    .iter()
+                    .skip(usize::try_from(offset).unwrap_or_default())
+                    .take(usize::try_from(limit).unwrap_or_default())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn download(&self, location: PhotoLocation) -> Result<Vec<u8>> {
+        let photo_id = match location {
+            PhotoLocation::Photo(photo) => photo.id,
+            PhotoLocation::ProfilePhoto { photo_id, .. } => photo_id,
+        };
+        self.record(FakeCall::Download { photo_id });
+        Ok(photo_id.to_string().into_bytes())
+    }
+
+    fn export_session(&self) -> Vec<u8> {
+        self.record(FakeCall::ExportSession);
+        b"fake session".to_vec()
+    }
+}
@@ -0,0 +1,589 @@
+use crate::{auth::SentCode, error::SignInFailure, user::Timestamp, Error, Result};
+use grammers_client::{
+    types::{Downloadable, Media, PasswordToken, Photo, UserProfilePhoto},
+    Client, Config, InitParams, SignInError,
+};
+use grammers_mtsender::InvocationError;
+use grammers_session::Session;
+use grammers_tl_types as tl;
+use std::{future::Future, io, net::Ipv4Addr};
+use tl::{
+    enums::{InputContact, InputUser},
+    types::{
+        contacts::{Contacts, ImportedContacts, ResolvedPeer},
+        UserFull,
+    },
+};
+use tracing::trace;
+
+#[cfg(feature = "fake")]
+mod fake;
+
+#[cfg(feature = "fake")]
+pub use self::fake::*;
+
+/// The step reached after submitting the login code (see [`Transport::sign_in`])
+pub enum SignInStep<P> {
+    /// The user is signed in
+    Complete,
+    /// The user has a password set up, which must be checked with [`Transport::check_password`]
+    PasswordRequired {
+        /// The token to provide to [`Transport::check_password`]
+        token: P,
+        /// The hint the user set up for their password
+        hint: Option<String>,
+    },
+}
+
+/// The step reached after exporting or importing a login token (see
+/// [`Transport::export_login_token`])
+pub enum QrLoginStep<P> {
+    /// The login token to display as a QR code, until it is accepted or expires
+    Token {
+        /// The login token
+        token: Vec<u8>,
+        /// When the login token expires and a new one must be exported
+        expires: Timestamp,
+    },
+    /// The login token was accepted by an account of another data center, where it must be
+    /// imported with [`Transport::import_login_token`]
+    MigrateTo {
+        /// The data center of the account
+        dc_id: i32,
+        /// The login token to import
+        token: Vec<u8>,
+    },
+    /// The login token was accepted
+    Accepted(SignInStep<P>),
+}
+
+/// The data center [`Client`] connects to when the session has no home data center yet
+const DEFAULT_DC: i32 = 2;
+/// Addresses of telegram's test data centers, indexed by data center id
+const TEST_DC_ADDRESSES: [(Ipv4Addr, u16); 4] = [
+    (Ipv4Addr::UNSPECIFIED, 0),
+    (Ipv4Addr::new(149, 154, 175, 10), 443),
+    (Ipv4Addr::new(149, 154, 167, 40), 443),
+    (Ipv4Addr::new(149, 154, 175, 117), 443),
+];
+
+/// What [`Client`] connects to telegram with, kept to reconnect to another data center while
+/// signing in (see [`crate::builder::UserRetrieverBuilder`])
+#[derive(Clone, Default)]
+pub struct ConnectParams {
+    /// The developer's API id
+    pub api_id: i32,
+    /// The developer's API hash
+    pub api_hash: String,
+    /// The [`InitParams`] sent when connecting, including the proxy and server address if any
+    pub init_params: InitParams,
+    /// Whether to connect to telegram's test data centers rather than the production ones.
+    /// Ignored if [`InitParams::server_addr`] is set
+    pub test_servers: bool,
+}
+
+impl ConnectParams {
+    /// Returns the [`Config`] connecting `session` to its home data center
+    pub(crate) fn config(&self, session: Session) -> Config {
+        let mut params = self.init_params.clone();
+        if self.test_servers && params.server_addr.is_none() {
+            // grammers only knows the production addresses, so the test data center of the
+            // session is set explicitly
+            let dc_id = session.get_user().map_or(DEFAULT_DC, |user| user.dc);
+            let address = usize::try_from(dc_id)
+                .ok()
+                .and_then(|dc_id| TEST_DC_ADDRESSES.get(dc_id))
+                .map_or(TEST_DC_ADDRESSES[DEFAULT_DC as usize], |address| *address);
+            params.server_addr = Some(address.into());
+        }
+        Config {
+            session,
+            api_id: self.api_id,
+            api_hash: self.api_hash.clone(),
+            params,
+        }
+    }
+}
+
+/// The [`Transport::LoginToken`] of [`Client`], identifying the login code sent to a phone number
+pub struct LoginCodeToken {
+    phone: String,
+    phone_code_hash: String,
+}
+
+/// A photo to download (see [`Transport::download`])
+pub enum PhotoLocation {
+    /// A photo returned by `photos.GetUserPhotos`
+    Photo(tl::types::Photo),
+    /// The current profile photo of an account
+    ProfilePhoto {
+        /// The id of the account
+        user_id: i64,
+        /// The access hash of the account
+        access_hash: i64,
+        /// The id of the photo
+        photo_id: i64,
+        /// The datacenter the photo is stored in
+        dc_id: i32,
+    },
+}
+
+/// The operations [`crate::UserRetriever`] needs from the telegram API
+///
+/// This is implemented for [`grammers_client::Client`], and can be implemented by other types to
+/// run the library against something else than telegram (see `FakeTransport` with the `fake`
+/// feature enabled)
+pub trait Transport: Sync {
+    /// Token returned when requesting the login code, required to sign in
+    type LoginToken: Send + Sync;
+    /// Token returned when a password is required to sign in
+    type PasswordToken: Send;
+
+    /// Whether the session is already authorized
+    fn is_authorized(&self) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Requests a login code to be sent to `phone` with `auth.SendCode`. If the account belongs to
+    /// another data center, it becomes the home data center of the session
+    fn request_login_code(
+        &mut self,
+        phone: &str,
+        params: &ConnectParams,
+    ) -> impl Future<Output = Result<(Self::LoginToken, SentCode)>> + Send;
+
+    /// Requests the login code to be sent again with `auth.ResendCode`, usually through
+    /// [`SentCode::next`]
+    fn resend_login_code(
+        &self,
+        token: &Self::LoginToken,
+    ) -> impl Future<Output = Result<(Self::LoginToken, SentCode)>> + Send;
+
+    /// Signs in with the `code` received after [`Transport::request_login_code`]
+    fn sign_in(
+        &self,
+        token: &Self::LoginToken,
+        code: &str,
+    ) -> impl Future<Output = Result<SignInStep<Self::PasswordToken>>> + Send;
+
+    /// Sends `account.GetPassword`, returning a new token to check the user's password with after
+    /// an invalid password
+    fn get_password(&self) -> impl Future<Output = Result<Self::PasswordToken>> + Send;
+
+    /// Finishes signing in with the user's `password`
+    fn check_password(
+        &self,
+        token: Self::PasswordToken,
+        password: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Sends `auth.ExportLoginToken`, returning a login token to display until it is accepted
+    /// from a device where the user is already signed in
+    fn export_login_token(
+        &self,
+        params: &ConnectParams,
+    ) -> impl Future<Output = Result<QrLoginStep<Self::PasswordToken>>> + Send;
+
+    /// Sends `auth.ImportLoginToken` to the data center `dc_id`, after
+    /// [`Transport::export_login_token`] returned [`QrLoginStep::MigrateTo`]. The data center
+    /// becomes the home data center of the session
+    fn import_login_token(
+        &mut self,
+        dc_id: i32,
+        token: Vec<u8>,
+        params: &ConnectParams,
+    ) -> impl Future<Output = Result<QrLoginStep<Self::PasswordToken>>> + Send;
+
+    /// Sends `auth.LogOut`, invalidating the session
+    fn log_out(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Sends `contacts.ImportContacts`
+    fn import_contacts(
+        &self,
+        contacts: Vec<InputContact>,
+    ) -> impl Future<Output = Result<ImportedContacts>> + Send;
+
+    /// Sends `contacts.DeleteContacts`
+    fn delete_contacts(&self, users: Vec<InputUser>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Sends `contacts.GetContacts`
+    fn get_contacts(&self) -> impl Future<Output = Result<Contacts>> + Send;
+
+    /// Sends `contacts.ResolvePhone`
+    fn resolve_phone(&self, phone: &str) -> impl Future<Output = Result<ResolvedPeer>> + Send;
+
+    /// Sends `contacts.ResolveUsername`
+    fn resolve_username(&self, username: &str)
+        -> impl Future<Output = Result<ResolvedPeer>> + Send;
+
+    /// Sends `users.GetUsers`
+    fn get_users(
+        &self,
+        users: Vec<InputUser>,
+    ) -> impl Future<Output = Result<Vec<tl::enums::User>>> + Send;
+
+    /// Sends `users.GetFullUser`
+    fn get_full_user(&self, user: InputUser) -> impl Future<Output = Result<UserFull>> + Send;
+
+    /// Sends `photos.GetUserPhotos`, skipping empty photos
+    fn get_user_photos(
+        &self,
+        user: InputUser,
+        offset: i32,
+        limit: i32,
+    ) -> impl Future<Output = Result<Vec<tl::types::Photo>>> + Send;
+
+    /// Downloads the largest size of the photo at `location`
+    fn download(&self, location: PhotoLocation) -> impl Future<Output = Result<Vec<u8>>> + Send;
+
+    /// Returns the current session, serialized so it can be saved to a
+    /// [`crate::session::SessionStore`]
+    fn export_session(&self) -> Vec<u8>;
+}
+
+impl Transport for Client {
+    type LoginToken = LoginCodeToken;
+    type PasswordToken = PasswordToken;
+
+    async fn is_authorized(&self) -> Result<bool> {
+        Ok(Self::is_authorized(self).await?)
+    }
+
+    async fn request_login_code(
+        &mut self,
+        phone: &str,
+        params: &ConnectParams,
+    ) -> Result<(LoginCodeToken, SentCode)> {
+        let request = tl::functions::auth::SendCode {
+            phone_number: phone.to_string(),
+            api_id: params.api_id,
+            api_hash: params.api_hash.clone(),
+            settings: tl::types::CodeSettings {
+                allow_flashcall: false,
+                current_number: false,
+                allow_app_hash: false,
+                allow_missed_call: false,
+                allow_firebase: false,
+                logout_tokens: None,
+                token: None,
+                app_sandbox: None,
+            }
+            .into(),
+        };
+        let sent_code = match self.invoke(&request).await {
+            // the account belongs to another data center, `X` in `PHONE_MIGRATE_X`
+            Err(InvocationError::Rpc(e)) if e.code == 303 => {
+                let dc_id = e
+                    .value
+                    .and_then(|dc_id| i32::try_from(dc_id).ok())
+                    .ok_or(Error::UnexpectedResponse("migration without a data center"))?;
+                reconnect(self, dc_id, params).await?;
+                self.invoke(&request).await?
+            }
+            sent_code => sent_code?,
+        };
+        login_code(phone, sent_code)
+    }
+
+    async fn resend_login_code(
+        &self,
+        token: &LoginCodeToken,
+    ) -> Result<(LoginCodeToken, SentCode)> {
+        let sent_code = self
+            .invoke(&tl::functions::auth::ResendCode {
+                phone_number: token.phone.clone(),
+                phone_code_hash: token.phone_code_hash.clone(),
+            })
+            .await?;
+        login_code(&token.phone, sent_code)
+    }
+
+    async fn sign_in(
+        &self,
+        token: &LoginCodeToken,
+        code: &str,
+    ) -> Result<SignInStep<PasswordToken>> {
+        let response = self
+            .invoke(&tl::functions::auth::SignIn {
+                phone_number: token.phone.clone(),
+                phone_code_hash: token.phone_code_hash.clone(),
+                phone_code: Some(code.to_string()),
+                email_verification: None,
+            })
+            .await;
+        match response {
+            Ok(tl::enums::auth::Authorization::Authorization(authorization)) => {
+                record_user(self, &authorization);
+                Ok(SignInStep::Complete)
+            }
+            Ok(tl::enums::auth::Authorization::SignUpRequired(_)) => {
+                Err(Error::SignIn(SignInFailure::SignUpRequired))
+            }
+            Err(InvocationError::Rpc(e)) if e.name == "SESSION_PASSWORD_NEEDED" => {
+                password_required(self).await
+            }
+            Err(InvocationError::Rpc(e)) if e.name == "PHONE_CODE_EXPIRED" => {
+                Err(Error::SignIn(SignInFailure::CodeExpired))
+            }
+            Err(InvocationError::Rpc(e)) if e.name.starts_with("PHONE_CODE_") => {
+                Err(Error::SignIn(SignInFailure::InvalidCode))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_password(&self) -> Result<PasswordToken> {
+        let password = self.invoke(&tl::functions::account::GetPassword {}).await?;
+        Ok(PasswordToken::new(password.into()))
+    }
+
+    async fn check_password(&self, token: PasswordToken, password: &str) -> Result<()> {
+        match Self::check_password(self, token, password).await {
+            Ok(_) => Ok(()),
+            Err(SignInError::InvalidPassword) => Err(Error::SignIn(SignInFailure::InvalidPassword)),
+            Err(SignInError::Other(e)) => Err(e.into()),
+            // only returned by `Client::sign_in`
+            Err(
+                SignInError::SignUpRequired { .. }
+                | SignInError::PasswordRequired(_)
+                | SignInError::InvalidCode,
+            ) => Err(Error::UnexpectedResponse(
+                "sign in error when checking the password",
+            )),
+        }
+    }
+
+    async fn export_login_token(
+        &self,
+        params: &ConnectParams,
+    ) -> Result<QrLoginStep<PasswordToken>> {
+        let response = self
+            .invoke(&tl::functions::auth::ExportLoginToken {
+                api_id: params.api_id,
+                api_hash: params.api_hash.clone(),
+                except_ids: Vec::new(),
+            })
+            .await;
+        login_token_step(self, response).await
+    }
+
+    async fn import_login_token(
+        &mut self,
+        dc_id: i32,
+        token: Vec<u8>,
+        params: &ConnectParams,
+    ) -> Result<QrLoginStep<PasswordToken>> {
+        reconnect(self, dc_id, params).await?;
+        let response = self
+            .invoke(&tl::functions::auth::ImportLoginToken { token })
+            .await;
+        login_token_step(self, response).await
+    }
+
+    async fn log_out(&self) -> Result<()> {
+        Self::sign_out(self).await?;
+        Ok(())
+    }
+
+    async fn import_contacts(&self, contacts: Vec<InputContact>) -> Result<ImportedContacts> {
+        let tl::enums::contacts::ImportedContacts::Contacts(imported) = self
+            .invoke(&tl::functions::contacts::ImportContacts { contacts })
+            .await?;
+        Ok(imported)
+    }
+
+    async fn delete_contacts(&self, users: Vec<InputUser>) -> Result<()> {
+        let u = self
+            .invoke(&tl::functions::contacts::DeleteContacts { id: users })
+            .await?;
+        trace!("Updates: {u:?}");
+        Ok(())
+    }
+
+    async fn get_contacts(&self) -> Result<Contacts> {
+        match self
+            .invoke(&tl::functions::contacts::GetContacts { hash: 0 })
+            .await?
+        {
+            tl::enums::contacts::Contacts::Contacts(contacts) => Ok(contacts),
+            tl::enums::contacts::Contacts::NotModified => Err(Error::UnexpectedResponse(
+                "contacts.GetContacts returned NotModified",
+            )),
+        }
+    }
+
+    async fn resolve_phone(&self, phone: &str) -> Result<ResolvedPeer> {
+        let tl::enums::contacts::ResolvedPeer::Peer(resolved) = self
+            .invoke(&tl::functions::contacts::ResolvePhone {
+                phone: phone.to_string(),
+            })
+            .await?;
+        Ok(resolved)
+    }
+
+    async fn resolve_username(&self, username: &str) -> Result<ResolvedPeer> {
+        let tl::enums::contacts::ResolvedPeer::Peer(resolved) = self
+            .invoke(&tl::functions::contacts::ResolveUsername {
+                username: username.to_string(),
+            })
+            .await?;
+        Ok(resolved)
+    }
+
+    async fn get_users(&self, users: Vec<InputUser>) -> Result<Vec<tl::enums::User>> {
+        Ok(self
+            .invoke(&tl::functions::users::GetUsers { id: users })
+            .await?)
+    }
+
+    async fn get_full_user(&self, user: InputUser) -> Result<UserFull> {
+        let tl::enums::users::UserFull::Full(full) = self
+            .invoke(&tl::functions::users::GetFullUser { id: user })
+            .await?;
+        let tl::enums::UserFull::Full(full_user) = full.full_user;
+        Ok(full_user)
+    }
+
+    async fn get_user_photos(
+        &self,
+        user: InputUser,
+        offset: i32,
+        limit: i32,
+    ) -> Result<Vec<tl::types::Photo>> {
+        let photos = match self
+            .invoke(&tl::functions::photos::GetUserPhotos {
+                user_id: user,
+                offset,
+                max_id: 0,
+                limit,
+            })
+            .await?
+        {
+            tl::enums::photos::Photos::Photos(photos) => photos.photos,
+            tl::enums::photos::Photos::Slice(photos) => photos.photos,
+        };
+        Ok(photos
+            .into_iter()
+            .filter_map(|photo| match photo {
+                tl::enums::Photo::Photo(photo) => Some(photo),
+                tl::enums::Photo::Empty(_) => None,
+            })
+            .collect())
+    }
+
+    async fn download(&self, location: PhotoLocation) -> Result<Vec<u8>> {
+        let downloadable = match location {
+            PhotoLocation::Photo(photo) => Downloadable::Media(Media::Photo(Photo::from_raw(
+                tl::enums::Photo::Photo(photo),
+                self.clone(),
+            ))),
+            PhotoLocation::ProfilePhoto {
+                user_id,
+                access_hash,
+                photo_id,
+                dc_id,
+            } => Downloadable::UserProfilePhoto(UserProfilePhoto {
+                big: true,
+                peer: tl::types::InputPeerUser {
+                    user_id,
+                    access_hash,
+                }
+                .into(),
+                photo: tl::types::UserProfilePhoto {
+                    has_video: false,
+                    personal: false,
+                    photo_id,
+                    stripped_thumb: None,
+                    dc_id,
+                },
+            }),
+        };
+        let mut download = self.iter_download(&downloadable);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = download.next().await? {
+            bytes.extend(chunk);
+        }
+        Ok(bytes)
+    }
+
+    fn export_session(&self) -> Vec<u8> {
+        self.session().save()
+    }
+}
+
+/// Reconnects `client` to the data center `dc_id`, which becomes the home data center of the
+/// session
+async fn reconnect(client: &mut Client, dc_id: i32, params: &ConnectParams) -> Result<()> {
+    // grammers connects to the data center of the signed in user, which is only known once
+    // signed in, so a placeholder user is recorded until then (see `record_user`)
+    let session = Session::load(&client.session().save())
+        .map_err(|e| Error::Session(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    session.set_user(0, dc_id, false);
+    *client = Client::connect(params.config(session)).await?;
+    Ok(())
+}
+
+/// Records the signed in user in the session of `client`, as grammers does when signing in
+/// through its own methods
+fn record_user(client: &Client, authorization: &tl::types::auth::Authorization) {
+    let session = client.session();
+    let dc_id = session.get_user().map_or(DEFAULT_DC, |user| user.dc);
+    session.set_user(authorization.user.id(), dc_id, false);
+}
+
+/// Returns the [`SignInStep`] reached when telegram answers `SESSION_PASSWORD_NEEDED`
+async fn password_required(client: &Client) -> Result<SignInStep<PasswordToken>> {
+    let token = Transport::get_password(client).await?;
+    let hint = token.hint().map(str::to_string);
+    Ok(SignInStep::PasswordRequired { token, hint })
+}
+
+/// Converts the response to `auth.SendCode` or `auth.ResendCode` into a [`LoginCodeToken`]
+fn login_code(
+    phone: &str,
+    sent_code: tl::enums::auth::SentCode,
+) -> Result<(LoginCodeToken, SentCode)> {
+    let tl::enums::auth::SentCode::Code(sent_code) = sent_code else {
+        return Err(Error::UnexpectedResponse(
+            "signed in before entering the code",
+        ));
+    };
+    let token = LoginCodeToken {
+        phone: phone.to_string(),
+        phone_code_hash: sent_code.phone_code_hash,
+    };
+    let sent_code = SentCode {
+        delivery: sent_code.r#type.into(),
+        next: sent_code.next_type.map(Into::into),
+    };
+    Ok((token, sent_code))
+}
+
+/// Converts the response to `auth.ExportLoginToken` or `auth.ImportLoginToken` into a
+/// [`QrLoginStep`], recording the signed in user in the session once the token is accepted
+async fn login_token_step(
+    client: &Client,
+    response: std::result::Result<tl::enums::auth::LoginToken, InvocationError>,
+) -> Result<QrLoginStep<PasswordToken>> {
+    use tl::enums::auth::{Authorization, LoginToken as Token};
+
+    match response {
+        Ok(Token::Token(token)) => Ok(QrLoginStep::Token {
+            token: token.token,
+            expires: token.expires.into(),
+        }),
+        Ok(Token::MigrateTo(migrate)) => Ok(QrLoginStep::MigrateTo {
+            dc_id: migrate.dc_id,
+            token: migrate.token,
+        }),
+        Ok(Token::Success(success)) => match success.authorization {
+            Authorization::Authorization(authorization) => {
+                record_user(client, &authorization);
+                Ok(QrLoginStep::Accepted(SignInStep::Complete))
+            }
+            Authorization::SignUpRequired(_) => Err(Error::SignIn(SignInFailure::SignUpRequired)),
+        },
+        Err(InvocationError::Rpc(e)) if e.name == "SESSION_PASSWORD_NEEDED" => {
+            password_required(client).await.map(QrLoginStep::Accepted)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,22 +1,22 @@
-use super::FromGrammersData;
+use super::{FromGrammersData, Timestamp};
 use grammers_tl_types::enums::EmojiStatus as EmojiStatusGramm;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EmojiStatus {
     Status(Status),
     Until(EmojiStatusUntil),
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Status {
     pub document_id: i64,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EmojiStatusUntil {
     pub document_id: i64,
-    pub until: i32,
+    pub until: Timestamp,
 }
 
 impl FromGrammersData for Option<EmojiStatus> {
@@ -30,7 +30,7 @@ impl FromGrammersData for Option<EmojiStatus> {
             })),
             EmojiStatusGramm::Until(u) => Some(EmojiStatus::Until(EmojiStatusUntil {
                 document_id: u.document_id,
-                until: u.until,
+                until: u.until.into(),
             })),
         }
     }
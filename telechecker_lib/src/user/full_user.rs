@@ -0,0 +1,79 @@
+use super::{FromGrammersData, PremiumGiftOption};
+use grammers_tl_types::{enums::Photo as PhotoGramm, types::UserFull as UserFullGramm};
+use serde::{Deserialize, Serialize};
+
+/// Local equivalent struct of [`UserFullGramm`], as returned by `users.GetFullUser`
+///
+/// The photos are reduced to their id; the peer, notification and bot settings, the wallpaper
+/// and the stories are left out. The birthday, personal channel and business details are not
+/// part of the TL layer used by [`grammers_tl_types`] and are therefore not available
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct FullUserData {
+    pub blocked: bool,
+    pub phone_calls_available: bool,
+    pub phone_calls_private: bool,
+    pub can_pin_message: bool,
+    pub has_scheduled: bool,
+    pub video_calls_available: bool,
+    pub voice_messages_forbidden: bool,
+    pub translations_disabled: bool,
+    pub stories_pinned_available: bool,
+    pub blocked_my_stories_from: bool,
+    pub id: i64,
+    pub about: Option<String>,
+    pub personal_photo_id: Option<i64>,
+    pub profile_photo_id: Option<i64>,
+    pub fallback_photo_id: Option<i64>,
+    pub pinned_msg_id: Option<i32>,
+    pub common_chats_count: i32,
+    pub folder_id: Option<i32>,
+    pub ttl_period: Option<i32>,
+    pub theme_emoticon: Option<String>,
+    pub private_forward_name: Option<String>,
+    pub premium_gifts: Option<Vec<PremiumGiftOption>>,
+}
+
+/// Returns the id of `photo`, or [`None`] if it is empty
+fn photo_id(photo: PhotoGramm) -> Option<i64> {
+    match photo {
+        PhotoGramm::Empty(_) => None,
+        PhotoGramm::Photo(p) => Some(p.id),
+    }
+}
+
+impl FromGrammersData for FullUserData {
+    type GrammersType = UserFullGramm;
+
+    fn from_grammers(grammers_data: Self::GrammersType) -> Self {
+        Self {
+            blocked: grammers_data.blocked,
+            phone_calls_available: grammers_data.phone_calls_available,
+            phone_calls_private: grammers_data.phone_calls_private,
+            can_pin_message: grammers_data.can_pin_message,
+            has_scheduled: grammers_data.has_scheduled,
+            video_calls_available: grammers_data.video_calls_available,
+            voice_messages_forbidden: grammers_data.voice_messages_forbidden,
+            translations_disabled: grammers_data.translations_disabled,
+            stories_pinned_available: grammers_data.stories_pinned_available,
+            blocked_my_stories_from: grammers_data.blocked_my_stories_from,
+            id: grammers_data.id,
+            about: grammers_data.about,
+            personal_photo_id: grammers_data.personal_photo.and_then(photo_id),
+            profile_photo_id: grammers_data.profile_photo.and_then(photo_id),
+            fallback_photo_id: grammers_data.fallback_photo.and_then(photo_id),
+            pinned_msg_id: grammers_data.pinned_msg_id,
+            common_chats_count: grammers_data.common_chats_count,
+            folder_id: grammers_data.folder_id,
+            ttl_period: grammers_data.ttl_period,
+            theme_emoticon: grammers_data.theme_emoticon,
+            private_forward_name: grammers_data.private_forward_name,
+            premium_gifts: grammers_data.premium_gifts.map(|gifts| {
+                gifts
+                    .into_iter()
+                    .map(PremiumGiftOption::from_grammers)
+                    .collect()
+            }),
+        }
+    }
+}
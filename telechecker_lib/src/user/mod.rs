@@ -4,16 +4,20 @@
 #![allow(clippy::module_name_repetitions)]
 
 use grammers_tl_types::{enums::InputUser, types::User};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 mod emoji_status;
+mod full_user;
+mod premium_gift_option;
 mod restriction_reason;
+mod timestamp;
 mod user_profile_photo;
 mod user_status;
 mod username;
 
 pub use self::{
-    emoji_status::*, restriction_reason::*, user_profile_photo::*, user_status::*, username::*,
+    emoji_status::*, full_user::*, premium_gift_option::*, restriction_reason::*, timestamp::*,
+    user_profile_photo::*, user_status::*, username::*,
 };
 
 impl From<User> for UserData {
@@ -22,6 +26,12 @@ impl From<User> for UserData {
     }
 }
 
+impl From<grammers_tl_types::types::UserFull> for FullUserData {
+    fn from(value: grammers_tl_types::types::UserFull) -> Self {
+        Self::from_grammers(value)
+    }
+}
+
 impl From<&UserData> for grammers_tl_types::types::InputUser {
     fn from(value: &UserData) -> Self {
         Self {
@@ -39,7 +49,7 @@ impl From<&UserData> for InputUser {
 }
 
 /// Local equivalent struct of [User]
-#[derive(Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct UserData {
     pub is_self: bool,
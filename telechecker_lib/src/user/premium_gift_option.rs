@@ -0,0 +1,28 @@
+use super::FromGrammersData;
+use grammers_tl_types::enums::PremiumGiftOption as PremiumGiftOptionGramm;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PremiumGiftOption {
+    pub months: i32,
+    pub currency: String,
+    pub amount: i64,
+    pub bot_url: String,
+    pub store_product: Option<String>,
+}
+
+impl FromGrammersData for PremiumGiftOption {
+    type GrammersType = PremiumGiftOptionGramm;
+
+    fn from_grammers(grammers_data: Self::GrammersType) -> Self {
+        match grammers_data {
+            PremiumGiftOptionGramm::Option(o) => Self {
+                months: o.months,
+                currency: o.currency,
+                amount: o.amount,
+                bot_url: o.bot_url,
+                store_product: o.store_product,
+            },
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use super::FromGrammersData;
 use grammers_tl_types::enums::RestrictionReason as RestrictionReasonGramm;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RestrictionReason {
     pub platform: String,
     pub reason: String,
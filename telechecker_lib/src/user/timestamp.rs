@@ -0,0 +1,90 @@
+use chrono::{DateTime, SecondsFormat};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A point in time, sent by telegram as unix seconds
+///
+/// Serialized as an RFC 3339 string (i.e. `2023-11-14T22:13:20Z`), or, with the
+/// `raw-timestamps` feature, as `{"unix": 1700000000, "rfc3339": "2023-11-14T22:13:20Z"}`. All
+/// of these, as well as bare unix seconds, are accepted when deserializing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    /// Returns the [`Timestamp`] `unix` seconds after the unix epoch
+    #[must_use]
+    pub const fn from_unix(unix: i64) -> Self {
+        Self(unix)
+    }
+
+    /// Returns the current [`Timestamp`]
+    #[must_use]
+    pub fn now() -> Self {
+        let unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self(i64::try_from(unix).unwrap_or(i64::MAX))
+    }
+
+    /// Returns the amount of seconds since the unix epoch
+    #[must_use]
+    pub const fn unix(self) -> i64 {
+        self.0
+    }
+
+    /// Returns the timestamp formatted as RFC 3339 in UTC, or [`None`] if it is out of range
+    #[must_use]
+    pub fn to_rfc3339(self) -> Option<String> {
+        DateTime::from_timestamp(self.0, 0)
+            .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+}
+
+impl From<i32> for Timestamp {
+    fn from(value: i32) -> Self {
+        Self(value.into())
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(rfc3339) = self.to_rfc3339() else {
+            return serializer.serialize_i64(self.0);
+        };
+        #[cfg(feature = "raw-timestamps")]
+        {
+            use serde::ser::SerializeStruct;
+
+            let mut timestamp = serializer.serialize_struct("Timestamp", 2)?;
+            timestamp.serialize_field("unix", &self.0)?;
+            timestamp.serialize_field("rfc3339", &rfc3339)?;
+            timestamp.end()
+        }
+        #[cfg(not(feature = "raw-timestamps"))]
+        serializer.serialize_str(&rfc3339)
+    }
+}
+
+/// Every format a [`Timestamp`] can be read from
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyTimestamp {
+    /// Files written before timestamps were typed, holding only unix seconds
+    Unix(i64),
+    Rfc3339(String),
+    Raw {
+        unix: i64,
+    },
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match AnyTimestamp::deserialize(deserializer)? {
+            AnyTimestamp::Unix(unix) | AnyTimestamp::Raw { unix } => Ok(Self(unix)),
+            AnyTimestamp::Rfc3339(rfc3339) => DateTime::parse_from_rfc3339(&rfc3339)
+                .map(|date| Self(date.timestamp()))
+                .map_err(de::Error::custom),
+        }
+    }
+}
@@ -1,14 +1,120 @@
 use super::FromGrammersData;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use grammers_tl_types::enums::UserProfilePhoto as UserProfilePhotoGramm;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+/// The JPEG header telegram strips from every stripped thumbnail, as only its dimensions differ
+/// between thumbnails (see [`stripped_thumb_to_jpeg`])
+#[rustfmt::skip]
+const STRIPPED_THUMB_HEADER: [u8; 623] = [
+    0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, 0x4a, 0x46, 0x49, 0x46, 0x00, 0x01,
+    0x01, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0xff, 0xdb, 0x00, 0x43,
+    0x00, 0x28, 0x1c, 0x1e, 0x23, 0x1e, 0x19, 0x28, 0x23, 0x21, 0x23, 0x2d,
+    0x2b, 0x28, 0x30, 0x3c, 0x64, 0x41, 0x3c, 0x37, 0x37, 0x3c, 0x7b, 0x58,
+    0x5d, 0x49, 0x64, 0x91, 0x80, 0x99, 0x96, 0x8f, 0x80, 0x8c, 0x8a, 0xa0,
+    0xb4, 0xe6, 0xc3, 0xa0, 0xaa, 0xda, 0xad, 0x8a, 0x8c, 0xc8, 0xff, 0xcb,
+    0xda, 0xee, 0xf5, 0xff, 0xff, 0xff, 0x9b, 0xc1, 0xff, 0xff, 0xff, 0xfa,
+    0xff, 0xe6, 0xfd, 0xff, 0xf8, 0xff, 0xdb, 0x00, 0x43, 0x01, 0x2b, 0x2d,
+    0x2d, 0x3c, 0x35, 0x3c, 0x76, 0x41, 0x41, 0x76, 0xf8, 0xa5, 0x8c, 0xa5,
+    0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8,
+    0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8,
+    0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8,
+    0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8,
+    0xf8, 0xf8, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x01, 0x22, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01, 0xff, 0xc4, 0x00,
+    0x1f, 0x00, 0x00, 0x01, 0x05, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05,
+    0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0xff, 0xc4, 0x00, 0xb5, 0x10, 0x00,
+    0x02, 0x01, 0x03, 0x03, 0x02, 0x04, 0x03, 0x05, 0x05, 0x04, 0x04, 0x00,
+    0x00, 0x01, 0x7d, 0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21,
+    0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07, 0x22, 0x71, 0x14, 0x32, 0x81,
+    0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0, 0x24,
+    0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+    0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a,
+    0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56,
+    0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a,
+    0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86,
+    0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+    0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3,
+    0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6,
+    0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9,
+    0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1,
+    0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xff, 0xc4, 0x00,
+    0x1f, 0x01, 0x00, 0x03, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05,
+    0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0xff, 0xc4, 0x00, 0xb5, 0x11, 0x00,
+    0x02, 0x01, 0x02, 0x04, 0x04, 0x03, 0x04, 0x07, 0x05, 0x04, 0x04, 0x00,
+    0x01, 0x02, 0x77, 0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31,
+    0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71, 0x13, 0x22, 0x32, 0x81, 0x08,
+    0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0, 0x15,
+    0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+    0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39,
+    0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55,
+    0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84,
+    0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+    0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa,
+    0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4,
+    0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7,
+    0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea,
+    0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xff, 0xda, 0x00,
+    0x0c, 0x03, 0x01, 0x00, 0x02, 0x11, 0x03, 0x11, 0x00, 0x3f, 0x00,
+];
+/// The JPEG footer telegram strips from every stripped thumbnail
+const STRIPPED_THUMB_FOOTER: [u8; 2] = [0xff, 0xd9];
+/// The offset of the height in [`STRIPPED_THUMB_HEADER`]
+const STRIPPED_THUMB_HEIGHT: usize = 164;
+/// The offset of the width in [`STRIPPED_THUMB_HEADER`]
+const STRIPPED_THUMB_WIDTH: usize = 166;
+
+/// Function that reconstructs the JPEG a stripped thumbnail (see
+/// [`UserProfilePhoto::stripped_thumb`]) was made from
+///
+/// A stripped thumbnail is a tiny JPEG without its header and footer, prefixed with a version
+/// (always `1`), its height and its width
+///
+/// Returns [`None`] if `stripped` is not a stripped thumbnail
+#[must_use]
+pub fn stripped_thumb_to_jpeg(stripped: &[u8]) -> Option<Vec<u8>> {
+    let [1, height, width, data @ ..] = stripped else {
+        return None;
+    };
+    let mut jpeg =
+        Vec::with_capacity(STRIPPED_THUMB_HEADER.len() + data.len() + STRIPPED_THUMB_FOOTER.len());
+    jpeg.extend_from_slice(&STRIPPED_THUMB_HEADER);
+    jpeg[STRIPPED_THUMB_HEIGHT] = *height;
+    jpeg[STRIPPED_THUMB_WIDTH] = *width;
+    jpeg.extend_from_slice(data);
+    jpeg.extend_from_slice(&STRIPPED_THUMB_FOOTER);
+    Some(jpeg)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UserProfilePhoto {
     pub has_video: bool,
     pub personal: bool,
     pub photo_id: i64,
     pub stripped_thumb: Option<Vec<u8>>,
     pub dc_id: i32,
+    /// The stripped thumbnail as a `data:image/jpeg;base64,` URI, if requested (see
+    /// [`crate::UserRetriever::with_thumbnails`])
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+}
+
+impl UserProfilePhoto {
+    /// Returns the stripped thumbnail as a JPEG (see [`stripped_thumb_to_jpeg`])
+    #[must_use]
+    pub fn thumbnail_jpeg(&self) -> Option<Vec<u8>> {
+        stripped_thumb_to_jpeg(self.stripped_thumb.as_deref()?)
+    }
+
+    /// Returns the stripped thumbnail as a `data:image/jpeg;base64,` URI
+    #[must_use]
+    pub fn thumbnail_data_uri(&self) -> Option<String> {
+        let jpeg = self.thumbnail_jpeg()?;
+        Some(format!("data:image/jpeg;base64,{}", STANDARD.encode(jpeg)))
+    }
 }
 
 impl FromGrammersData for Option<UserProfilePhoto> {
@@ -23,7 +129,59 @@ impl FromGrammersData for Option<UserProfilePhoto> {
                 photo_id: user_profile_photo.photo_id,
                 stripped_thumb: user_profile_photo.stripped_thumb,
                 dc_id: user_profile_photo.dc_id,
+                thumbnail: None,
             }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(stripped_thumb: Option<Vec<u8>>) -> UserProfilePhoto {
+        UserProfilePhoto {
+            has_video: false,
+            personal: false,
+            photo_id: 1,
+            stripped_thumb,
+            dc_id: 2,
+            thumbnail: None,
+        }
+    }
+
+    #[test]
+    fn stripped_thumb_is_wrapped_in_the_jpeg_header_and_footer() {
+        let jpeg = stripped_thumb_to_jpeg(&[1, 40, 30, 0xaa, 0xbb]).unwrap();
+
+        assert_eq!(jpeg.len(), STRIPPED_THUMB_HEADER.len() + 2 + 2);
+        assert_eq!(jpeg[164], 40);
+        assert_eq!(jpeg[166], 30);
+        assert_eq!(jpeg[..164], STRIPPED_THUMB_HEADER[..164]);
+        assert_eq!(jpeg[165], STRIPPED_THUMB_HEADER[165]);
+        assert_eq!(jpeg[167..623], STRIPPED_THUMB_HEADER[167..]);
+        assert_eq!(jpeg[623..], [0xaa, 0xbb, 0xff, 0xd9]);
+    }
+
+    #[test]
+    fn invalid_stripped_thumbs_are_rejected() {
+        assert_eq!(stripped_thumb_to_jpeg(&[2, 40, 30, 0xaa]), None);
+        assert_eq!(stripped_thumb_to_jpeg(&[1, 40]), None);
+        assert_eq!(stripped_thumb_to_jpeg(&[]), None);
+        assert_eq!(photo(Some(vec![0, 40, 30])).thumbnail_data_uri(), None);
+        assert_eq!(photo(None).thumbnail_data_uri(), None);
+    }
+
+    #[test]
+    fn thumbnail_data_uri_encodes_the_jpeg() {
+        let photo = photo(Some(vec![1, 40, 30, 0xaa]));
+
+        let uri = photo.thumbnail_data_uri().unwrap();
+
+        let encoded = uri.strip_prefix("data:image/jpeg;base64,").unwrap();
+        assert_eq!(
+            STANDARD.decode(encoded).unwrap(),
+            photo.thumbnail_jpeg().unwrap()
+        );
+    }
+}
@@ -1,10 +1,10 @@
-use super::FromGrammersData;
+use super::{FromGrammersData, Timestamp};
 use grammers_tl_types::enums::UserStatus as UserStatusGramm;
 use grammers_tl_types::types::UserStatusOffline as UserStatusOfflineGramm;
 use grammers_tl_types::types::UserStatusOnline as UserStatusOnlineGramm;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserStatus {
     Empty,
     Online(UserStatusOnline),
@@ -14,13 +14,46 @@ pub enum UserStatus {
     LastMonth,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UserStatusOnline {
-    pub expires: i32,
+    pub expires: Timestamp,
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UserStatusOffline {
-    pub was_online: i32,
+    pub was_online: Timestamp,
+}
+
+impl UserStatus {
+    /// Returns when the account was last seen as of `at`, as shown by telegram clients (i.e.
+    /// `online`, `5 hours ago` or `within a week`)
+    #[must_use]
+    pub fn last_seen(&self, at: Timestamp) -> String {
+        match self {
+            Self::Empty => "a long time ago".to_string(),
+            Self::Online(UserStatusOnline { expires }) if *expires > at => "online".to_string(),
+            Self::Online(UserStatusOnline { expires: seen })
+            | Self::Offline(UserStatusOffline { was_online: seen }) => ago(at.unix() - seen.unix()),
+            Self::Recently => "recently".to_string(),
+            Self::LastWeek => "within a week".to_string(),
+            Self::LastMonth => "within a month".to_string(),
+        }
+    }
+}
+
+/// Formats `seconds` as the largest whole unit of time ago (i.e. `5 hours ago`)
+fn ago(seconds: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    let (amount, unit) = match seconds {
+        ..MINUTE => return "just now".to_string(),
+        MINUTE..HOUR => (seconds / MINUTE, "minute"),
+        HOUR..DAY => (seconds / HOUR, "hour"),
+        DAY.. => (seconds / DAY, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{amount} {unit}{plural} ago")
 }
 
 impl FromGrammersData for UserStatus {
@@ -30,10 +63,14 @@ impl FromGrammersData for UserStatus {
         match grammers_data {
             UserStatusGramm::Empty => Self::Empty,
             UserStatusGramm::Online(UserStatusOnlineGramm { expires }) => {
-                Self::Online(UserStatusOnline { expires })
+                Self::Online(UserStatusOnline {
+                    expires: expires.into(),
+                })
             }
             UserStatusGramm::Offline(UserStatusOfflineGramm { was_online }) => {
-                Self::Offline(UserStatusOffline { was_online })
+                Self::Offline(UserStatusOffline {
+                    was_online: was_online.into(),
+                })
             }
             UserStatusGramm::Recently => Self::Recently,
             UserStatusGramm::LastWeek => Self::LastWeek,
@@ -41,3 +78,70 @@ impl FromGrammersData for UserStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn offline(seconds_ago: i64) -> UserStatus {
+        UserStatus::Offline(UserStatusOffline {
+            was_online: Timestamp::from_unix(NOW - seconds_ago),
+        })
+    }
+
+    #[test]
+    fn ago_uses_the_largest_whole_unit() {
+        for (seconds, expected) in [
+            (0, "just now"),
+            (59, "just now"),
+            (60, "1 minute ago"),
+            (119, "1 minute ago"),
+            (120, "2 minutes ago"),
+            (3_599, "59 minutes ago"),
+            (3_600, "1 hour ago"),
+            (7_199, "1 hour ago"),
+            (7_200, "2 hours ago"),
+            (86_399, "23 hours ago"),
+            (86_400, "1 day ago"),
+            (172_800, "2 days ago"),
+            (3_000_000, "34 days ago"),
+        ] {
+            assert_eq!(ago(seconds), expected, "{seconds}s");
+            assert_eq!(
+                offline(seconds).last_seen(Timestamp::from_unix(NOW)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn last_seen_describes_every_status() {
+        let now = Timestamp::from_unix(NOW);
+        for (status, expected) in [
+            (
+                UserStatus::Online(UserStatusOnline {
+                    expires: Timestamp::from_unix(NOW + 30),
+                }),
+                "online",
+            ),
+            (
+                UserStatus::Online(UserStatusOnline {
+                    expires: Timestamp::from_unix(NOW - 300),
+                }),
+                "5 minutes ago",
+            ),
+            (
+                UserStatus::Online(UserStatusOnline { expires: now }),
+                "just now",
+            ),
+            (UserStatus::Recently, "recently"),
+            (UserStatus::LastWeek, "within a week"),
+            (UserStatus::LastMonth, "within a month"),
+            (UserStatus::Empty, "a long time ago"),
+        ] {
+            assert_eq!(status.last_seen(now), expected, "{status:?}");
+        }
+    }
+}
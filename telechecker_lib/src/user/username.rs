@@ -1,8 +1,8 @@
 use super::FromGrammersData;
 use grammers_tl_types::enums::Username as UsernameGramm;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Username {
     pub editable: bool,
     pub active: bool,